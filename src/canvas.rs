@@ -0,0 +1,580 @@
+//! ウィンドウの大きさによらず、決まった大きさの画面に収めて出力する。
+
+use std::{collections::HashMap, f32::consts::PI, fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    draw::{self, Color},
+    frame_pool::FramePool,
+    window_capture::{CapturedFrame, PixelFormat},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+    Lanczos,
+}
+
+/// 縦横比が合わずに余ったところの塗り方
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LetterboxFill {
+    Color(Color),
+    /// フレームを画面いっぱいに広げてぼかしたもの
+    Blur,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CanvasSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CanvasConfig {
+    // None ならウィンドウの大きさのまま出す
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<CanvasSize>,
+    pub filter: ScaleFilter,
+    pub fill: LetterboxFill,
+}
+
+/// シェルからの設定の変更
+#[derive(Clone, Debug, PartialEq)]
+pub enum CanvasChange {
+    Size(Option<CanvasSize>),
+    Filter(ScaleFilter),
+    Fill(LetterboxFill),
+}
+
+impl Default for CanvasConfig {
+    fn default() -> Self {
+        Self {
+            size: None,
+            filter: ScaleFilter::Bilinear,
+            fill: LetterboxFill::Color(Color::BLACK),
+        }
+    }
+}
+
+impl CanvasConfig {
+    pub fn apply(&mut self, change: CanvasChange) {
+        match change {
+            CanvasChange::Size(size) => self.size = size,
+            CanvasChange::Filter(filter) => self.filter = filter,
+            CanvasChange::Fill(fill) => self.fill = fill,
+        }
+    }
+}
+
+/// 出力するフレームを作る。バッファは使い回す
+pub struct OutputCanvas {
+    pool: FramePool,
+    resampler: Resampler,
+}
+
+/// 拡大・縮小の重みと途中のバッファを、次のフレームのために取っておく
+#[derive(Default)]
+pub struct Resampler {
+    // (元の長さ, 出力の長さ, フィルター) ごとの重み
+    weights: HashMap<(usize, usize, ScaleFilter), Weights>,
+    horizontal: Vec<f32>,
+    acc: Vec<f32>,
+}
+
+impl Default for OutputCanvas {
+    fn default() -> Self {
+        Self {
+            pool: FramePool::new(4),
+            resampler: Resampler::default(),
+        }
+    }
+}
+
+impl OutputCanvas {
+    /// 縦横比を保ったまま画面の中央に収める。大きさが決まっていなければそのまま返す
+    pub fn fit(&mut self, frame: CapturedFrame, config: &CanvasConfig) -> CapturedFrame {
+        let Some(CanvasSize { width, height }) = config.size else {
+            return frame;
+        };
+        if frame.width == width && frame.height == height {
+            return frame.to_rgba8();
+        }
+
+        let src = frame.to_rgba8();
+        let scale = (width as f32 / src.width as f32).min(height as f32 / src.height as f32);
+        let fit_width = ((src.width as f32 * scale).round() as u32).clamp(1, width);
+        let fit_height = ((src.height as f32 * scale).round() as u32).clamp(1, height);
+        let (x, y) = ((width - fit_width) / 2, (height - fit_height) / 2);
+
+        let stride = width as usize * 4;
+        let mut bytes = self.pool.acquire(stride * height as usize);
+        if fit_width != width || fit_height != height {
+            match config.fill {
+                LetterboxFill::Color(color) => {
+                    draw::fill_rect(&mut bytes, width, height, (0, 0, width, height), color.0)
+                }
+                LetterboxFill::Blur => {
+                    blurred_backdrop(&mut self.resampler, &src, width, height, &mut bytes)
+                }
+            }
+        }
+
+        let start = y as usize * stride + x as usize * 4;
+        self.resampler.resample_into(
+            &src,
+            (fit_width, fit_height),
+            config.filter,
+            &mut bytes[start..],
+            stride,
+        );
+
+        CapturedFrame {
+            width,
+            height,
+            stride,
+            format: PixelFormat::Rgba8,
+            bytes: bytes.freeze(),
+            client_area: None,
+            ..frame
+        }
+    }
+}
+
+/// 画面を覆うようにフレームを広げてぼかしたものを dst に描く。小さくしてからぼかして手間を減らす
+fn blurred_backdrop(
+    resampler: &mut Resampler,
+    src: &CapturedFrame,
+    width: u32,
+    height: u32,
+    dst: &mut [u8],
+) {
+    let (small_width, small_height) = ((width / 16).max(1), (height / 16).max(1));
+    let scale =
+        (small_width as f32 / src.width as f32).max(small_height as f32 / src.height as f32);
+    let cover_width = ((src.width as f32 * scale).ceil() as u32).max(small_width);
+    let cover_height = ((src.height as f32 * scale).ceil() as u32).max(small_height);
+
+    let cover = resampler.resample(src, cover_width, cover_height, ScaleFilter::Bilinear);
+    let (x, y) = (
+        (cover_width - small_width) / 2,
+        (cover_height - small_height) / 2,
+    );
+    let mut small = Vec::with_capacity(small_width as usize * small_height as usize * 4);
+    for row in cover
+        .chunks_exact(cover_width as usize * 4)
+        .skip(y as usize)
+        .take(small_height as usize)
+    {
+        small.extend_from_slice(&row[x as usize * 4..(x + small_width) as usize * 4]);
+    }
+    draw::blur(&mut small, small_width, small_height, 2);
+
+    let small = CapturedFrame {
+        width: small_width,
+        height: small_height,
+        stride: small_width as usize * 4,
+        bytes: small.into(),
+        ..src.clone()
+    };
+    resampler.resample_into(
+        &small,
+        (width, height),
+        ScaleFilter::Bilinear,
+        dst,
+        width as usize * 4,
+    );
+}
+
+/// 詰め物のない RGBA8 のフレームを width x height に拡大・縮小する
+pub fn resample(src: &CapturedFrame, width: u32, height: u32, filter: ScaleFilter) -> Vec<u8> {
+    Resampler::default().resample(src, width, height, filter)
+}
+
+impl Resampler {
+    // 窓の大きさが変わるたびに増えるので、これを超えたら捨てて作り直す
+    const MAX_CACHED_WEIGHTS: usize = 16;
+
+    pub fn resample(
+        &mut self,
+        src: &CapturedFrame,
+        width: u32,
+        height: u32,
+        filter: ScaleFilter,
+    ) -> Vec<u8> {
+        let stride = width as usize * 4;
+        let mut bytes = vec![0; stride * height as usize];
+        self.resample_into(src, (width, height), filter, &mut bytes, stride);
+        bytes
+    }
+
+    /// dst の先頭から stride バイトごとに 1 行ずつ書く
+    pub fn resample_into(
+        &mut self,
+        src: &CapturedFrame,
+        (width, height): (u32, u32),
+        filter: ScaleFilter,
+        dst: &mut [u8],
+        stride: usize,
+    ) {
+        debug_assert!(src.is_packed_rgba8());
+        let (src_width, src_height) = (src.width as usize, src.height as usize);
+        let (width, height) = (width as usize, height as usize);
+        let row_len = width * 4;
+
+        if filter == ScaleFilter::Nearest {
+            for y in 0..height {
+                let sy = (y * src_height / height).min(src_height - 1);
+                let row = &src.bytes[sy * src_width * 4..(sy + 1) * src_width * 4];
+                let dst_row = &mut dst[y * stride..y * stride + row_len];
+                for (x, px) in dst_row.chunks_exact_mut(4).enumerate() {
+                    let sx = (x * src_width / width).min(src_width - 1);
+                    px.copy_from_slice(&row[sx * 4..sx * 4 + 4]);
+                }
+            }
+            return;
+        }
+
+        if self.weights.len() + 2 > Self::MAX_CACHED_WEIGHTS {
+            self.weights.clear();
+        }
+        for key in [(src_width, width, filter), (src_height, height, filter)] {
+            self.weights
+                .entry(key)
+                .or_insert_with(|| weights(key.0, key.1, key.2));
+        }
+        let columns = &self.weights[&(src_width, width, filter)];
+        let rows = &self.weights[&(src_height, height, filter)];
+
+        // 横、縦の順に一次元の畳み込みをする。途中の値はすべて上書きするので 0 で埋め直さない
+        self.horizontal.resize(row_len * src_height, 0.0);
+        for y in 0..src_height {
+            let src_row = &src.bytes[y * src_width * 4..(y + 1) * src_width * 4];
+            let dst_row = &mut self.horizontal[y * row_len..(y + 1) * row_len];
+            for (x, (start, ws)) in columns.iter().enumerate() {
+                let mut acc = [0f32; 4];
+                for (i, w) in ws.iter().enumerate() {
+                    let px = &src_row[(start + i) * 4..(start + i) * 4 + 4];
+                    for c in 0..4 {
+                        acc[c] += px[c] as f32 * w;
+                    }
+                }
+                dst_row[x * 4..x * 4 + 4].copy_from_slice(&acc);
+            }
+        }
+
+        self.acc.resize(row_len, 0.0);
+        for (y, (start, ws)) in rows.iter().enumerate() {
+            self.acc.iter_mut().for_each(|acc| *acc = 0.0);
+            for (i, w) in ws.iter().enumerate() {
+                let src_row = &self.horizontal[(start + i) * row_len..(start + i + 1) * row_len];
+                for (acc, src) in self.acc.iter_mut().zip(src_row) {
+                    *acc += src * w;
+                }
+            }
+
+            // Lanczos は負の重みがあるので範囲外に出ることがある
+            let dst_row = &mut dst[y * stride..y * stride + row_len];
+            for (dst, acc) in dst_row.iter_mut().zip(&self.acc) {
+                *dst = acc.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// 出力の各画素について、元のどこからどれだけの重みで取るか
+type Weights = Vec<(usize, Vec<f32>)>;
+
+fn weights(src_len: usize, dst_len: usize, filter: ScaleFilter) -> Weights {
+    let scale = src_len as f32 / dst_len as f32;
+    // 縮小するときはフィルターを広げて、間引かれる画素も拾う
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            let start = ((center - support).floor().max(0.0) as usize).min(src_len - 1);
+            let end = ((center + support).ceil() as usize).clamp(start + 1, src_len);
+            let mut ws: Vec<f32> = (start..end)
+                .map(|j| filter.kernel((j as f32 + 0.5 - center) / filter_scale))
+                .collect();
+
+            let sum: f32 = ws.iter().sum();
+            if sum.abs() < f32::EPSILON {
+                // 重みが打ち消し合ったら一番近い画素を使う
+                let nearest = (center as usize).clamp(start, end - 1);
+                ws.iter_mut().for_each(|w| *w = 0.0);
+                ws[nearest - start] = 1.0;
+            } else {
+                ws.iter_mut().for_each(|w| *w /= sum);
+            }
+
+            (start, ws)
+        })
+        .collect()
+}
+
+impl ScaleFilter {
+    fn support(self) -> f32 {
+        match self {
+            ScaleFilter::Nearest => 0.5,
+            ScaleFilter::Bilinear => 1.0,
+            ScaleFilter::Lanczos => 3.0,
+        }
+    }
+
+    fn kernel(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            ScaleFilter::Nearest => (x < 0.5) as u8 as f32,
+            ScaleFilter::Bilinear => (1.0 - x).max(0.0),
+            ScaleFilter::Lanczos if x < 3.0 => sinc(x) * sinc(x / 3.0),
+            ScaleFilter::Lanczos => 0.0,
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < f32::EPSILON {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl fmt::Display for ScaleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScaleFilter::Nearest => "nearest",
+            ScaleFilter::Bilinear => "bilinear",
+            ScaleFilter::Lanczos => "lanczos",
+        })
+    }
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            ScaleFilter::Nearest,
+            ScaleFilter::Bilinear,
+            ScaleFilter::Lanczos,
+        ]
+        .into_iter()
+        .find(|filter| filter.to_string() == s)
+        .ok_or_else(|| format!("unknown filter {s}, expected nearest, bilinear or lanczos"))
+    }
+}
+
+impl fmt::Display for LetterboxFill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LetterboxFill::Color(color) => write!(f, "{color}"),
+            LetterboxFill::Blur => f.write_str("blur"),
+        }
+    }
+}
+
+impl FromStr for LetterboxFill {
+    type Err = String;
+
+    /// `blur` か `#rrggbb`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blur" => Ok(LetterboxFill::Blur),
+            s => s.parse().map(LetterboxFill::Color),
+        }
+    }
+}
+
+impl fmt::Display for CanvasSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl CanvasSize {
+    // 1 枚のバッファを確保できる大きさにとどめる
+    pub const MAX: u32 = 8192;
+}
+
+impl FromStr for CanvasSize {
+    type Err = String;
+
+    /// `1920x1080`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let size = s.split_once('x').and_then(|(width, height)| {
+            Some(CanvasSize {
+                width: width.parse().ok()?,
+                height: height.parse().ok()?,
+            })
+        });
+
+        match size {
+            Some(size) if size.width > CanvasSize::MAX || size.height > CanvasSize::MAX => Err(
+                format!("canvas size must be at most {0}x{0}", CanvasSize::MAX),
+            ),
+            Some(size) if size.width > 0 && size.height > 0 => Ok(size),
+            _ => Err(format!(
+                "invalid canvas size {s}, expected <width>x<height>"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CanvasConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.size {
+            Some(size) => write!(f, "{size}, {}, fill {}", self.filter, self.fill),
+            None => f.write_str("off"),
+        }
+    }
+}
+
+// 設定ファイルでは文字列で書く
+macro_rules! serde_via_str {
+    ($($ty:ty),*) => {$(
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                s.parse().map_err(de::Error::custom)
+            }
+        }
+    )*};
+}
+
+serde_via_str!(LetterboxFill, CanvasSize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_window;
+    use std::time::Instant;
+
+    fn frame(width: u32, height: u32, bytes: Vec<u8>) -> CapturedFrame {
+        CapturedFrame {
+            id: mock_window(1),
+            width,
+            height,
+            stride: width as usize * 4,
+            format: PixelFormat::Rgba8,
+            timestamp: Instant::now(),
+            bytes: bytes.into(),
+            client_area: None,
+        }
+    }
+
+    fn sized(width: u32, height: u32, filter: ScaleFilter, fill: LetterboxFill) -> CanvasConfig {
+        CanvasConfig {
+            size: Some(CanvasSize { width, height }),
+            filter,
+            fill,
+        }
+    }
+
+    #[test]
+    fn letterboxes_to_fixed_size() {
+        let mut canvas = OutputCanvas::default();
+        let white = Color::WHITE;
+        let config = sized(8, 8, ScaleFilter::Bilinear, LetterboxFill::Color(white));
+
+        let out = canvas.fit(frame(4, 2, [10, 20, 30, 255].repeat(8)), &config);
+        assert_eq!((out.width, out.height), (8, 8));
+        assert_eq!(out.id, mock_window(1));
+        assert_eq!(out.pixel(0, 0), white.0);
+        assert_eq!(out.pixel(7, 7), white.0);
+        assert_eq!(out.pixel(4, 4), [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn unsized_canvas_passes_frames_through() {
+        let mut canvas = OutputCanvas::default();
+        let src = frame(3, 1, vec![1; 12]);
+
+        let out = canvas.fit(src.clone(), &CanvasConfig::default());
+        assert!(out.bytes.ptr_eq(&src.bytes));
+    }
+
+    #[test]
+    fn filters_interpolate_between_pixels() {
+        // 黒と白の 2 画素を 4 画素に広げる
+        #[rustfmt::skip]
+        let src = frame(2, 1, vec![0, 0, 0, 255, 200, 200, 200, 255]);
+
+        let red = |bytes: Vec<u8>| bytes.chunks_exact(4).map(|px| px[0]).collect::<Vec<_>>();
+        assert_eq!(
+            red(resample(&src, 4, 1, ScaleFilter::Nearest)),
+            [0, 0, 200, 200]
+        );
+        assert_eq!(
+            red(resample(&src, 4, 1, ScaleFilter::Bilinear)),
+            [0, 50, 150, 200]
+        );
+        let lanczos = red(resample(&src, 4, 1, ScaleFilter::Lanczos));
+        assert!(lanczos[0] < lanczos[1] && lanczos[1] < lanczos[2] && lanczos[2] < lanczos[3]);
+    }
+
+    #[test]
+    fn downscaling_averages_pixels() {
+        let src = frame(4, 1, [0, 0, 0, 255, 200, 200, 200, 255].repeat(2));
+
+        let out = resample(&src, 1, 1, ScaleFilter::Bilinear);
+        assert_eq!(out[0], 100);
+    }
+
+    #[test]
+    fn reused_resampler_matches_fresh_one() {
+        let mut resampler = Resampler::default();
+        let gradient = |width: u32| frame(width, 2, (0..width * 8).map(|i| i as u8).collect());
+
+        // 大きさが変わっても前の重みやバッファが混ざらない
+        for (src_width, width) in [(6, 4), (6, 4), (3, 8), (6, 4)] {
+            for filter in [ScaleFilter::Bilinear, ScaleFilter::Lanczos] {
+                let src = gradient(src_width);
+                assert_eq!(
+                    resampler.resample(&src, width, 3, filter),
+                    resample(&src, width, 3, filter)
+                );
+            }
+        }
+        assert_eq!(resampler.weights.len(), 6);
+    }
+
+    #[test]
+    fn blurred_fill_comes_from_frame() {
+        let mut canvas = OutputCanvas::default();
+        let config = sized(64, 32, ScaleFilter::Nearest, LetterboxFill::Blur);
+
+        let out = canvas.fit(frame(4, 4, [0, 100, 0, 255].repeat(16)), &config);
+        assert_eq!(out.pixel(0, 0), [0, 100, 0, 255]);
+    }
+
+    #[test]
+    fn parses_settings() {
+        let config: CanvasConfig =
+            toml::from_str("size = \"1920x1080\"\nfilter = \"lanczos\"\nfill = \"blur\"").unwrap();
+        assert_eq!(
+            config,
+            sized(1920, 1080, ScaleFilter::Lanczos, LetterboxFill::Blur)
+        );
+        assert_eq!(config.to_string(), "1920x1080, lanczos, fill blur");
+
+        assert!("1920".parse::<CanvasSize>().is_err());
+        assert!("0x1080".parse::<CanvasSize>().is_err());
+        assert!("8192x8192".parse::<CanvasSize>().is_ok());
+        assert!("65536x65536".parse::<CanvasSize>().is_err());
+        assert!("gray".parse::<LetterboxFill>().is_err());
+    }
+}
@@ -0,0 +1,447 @@
+//! 再起動しても残しておきたい設定。TOML ファイルに保存する。
+
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    canvas::CanvasConfig,
+    crop::Crop,
+    delay_line::DelayConfig,
+    focus_filter::FocusConfig,
+    mask::Mask,
+    placeholder::PlaceholderConfig,
+    redact::RedactConfig,
+    replay::ReplayConfig,
+    rule_expr::{same_process, RuleExpr},
+    transition::TransitionConfig,
+    window_list::WindowInfo,
+};
+
+pub const DEFAULT_PATH: &str = "obs-active-window-switcher.toml";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    // 読み込んだファイル。None なら保存しない
+    #[serde(skip)]
+    path: Option<PathBuf>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<WindowRule>,
+    // 許可ルールより優先される
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<WindowRule>,
+    #[serde(default)]
+    pub focus: FocusConfig,
+    #[serde(default)]
+    pub placeholder: PlaceholderConfig,
+    #[serde(default)]
+    pub transition: TransitionConfig,
+    #[serde(default)]
+    pub canvas: CanvasConfig,
+    #[serde(default)]
+    pub redact: RedactConfig,
+    #[serde(default)]
+    pub delay: DelayConfig,
+    #[serde(default)]
+    pub replay: ReplayConfig,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleKind {
+    Allow,
+    Deny,
+}
+
+/// ルールに照らした結果。番号は `list` に表示される 1 始まりのもの。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed(usize),
+    Denied(usize),
+    Unmatched,
+}
+
+/// ウィンドウのタイトル・クラス名・プロセス名と条件式によるルール。指定した項目がすべて一致したら当てはまる。
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<TitlePattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<RuleExpr>,
+    // 条件ではない。当てはまったウィンドウをこの範囲だけ出す
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Crop>,
+    // 条件ではない。当てはまったウィンドウのこの範囲を隠す
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub masks: Vec<Mask>,
+}
+
+/// タイトルの一部に一致する正規表現
+#[derive(Clone, Debug)]
+pub struct TitlePattern(Regex);
+
+impl Config {
+    /// ファイルがまだなければ空の設定を返す。保存するときはそのファイルに書き込む。
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut config: Config = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+        config.path = Some(path.to_owned());
+
+        Ok(config)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let text = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+
+        // 書いている途中で落ちたりディスクがいっぱいになったりしてもルールを失わないよう、
+        // 同じディレクトリの別のファイルに書き終えてから置き換える
+        let mut tmp_path = OsString::from(path);
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let res = (|| {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(text.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, path)
+        })();
+
+        res.map_err(|e| {
+            let _ = fs::remove_file(&tmp_path);
+            format!("{}: {e}", path.display())
+        })
+    }
+
+    pub fn rules(&self, kind: RuleKind) -> &[WindowRule] {
+        match kind {
+            RuleKind::Allow => &self.allow,
+            RuleKind::Deny => &self.deny,
+        }
+    }
+
+    pub fn rules_mut(&mut self, kind: RuleKind) -> &mut Vec<WindowRule> {
+        match kind {
+            RuleKind::Allow => &mut self.allow,
+            RuleKind::Deny => &mut self.deny,
+        }
+    }
+
+    /// 当てはまる許可ルールのうち、最初に切り抜きを持つものの切り抜き
+    pub fn crop(&self, info: &WindowInfo) -> Option<Crop> {
+        self.allow
+            .iter()
+            .filter(|rule| rule.matches(info))
+            .find_map(|rule| rule.crop)
+    }
+
+    /// 当てはまる許可ルールすべての隠す範囲
+    pub fn masks<'a>(&'a self, info: &'a WindowInfo) -> impl Iterator<Item = &'a Mask> {
+        self.allow
+            .iter()
+            .filter(|rule| rule.matches(info))
+            .flat_map(|rule| &rule.masks)
+    }
+
+    pub fn judge(&self, info: &WindowInfo) -> Verdict {
+        let position = |rules: &[WindowRule]| rules.iter().position(|rule| rule.matches(info));
+
+        if let Some(i) = position(&self.deny) {
+            Verdict::Denied(i + 1)
+        } else if let Some(i) = position(&self.allow) {
+            Verdict::Allowed(i + 1)
+        } else {
+            Verdict::Unmatched
+        }
+    }
+}
+
+impl RuleKind {
+    /// メッセージで "rule" の前に付ける語
+    pub fn prefix(self) -> &'static str {
+        match self {
+            RuleKind::Allow => "",
+            RuleKind::Deny => "deny ",
+        }
+    }
+}
+
+impl WindowRule {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.class.is_none()
+            && self.process.is_none()
+            && self.when.is_none()
+    }
+
+    pub fn matches(&self, info: &WindowInfo) -> bool {
+        // 何も指定していないルールですべてを許可してしまわないように
+        if self.is_empty() {
+            return false;
+        }
+
+        self.title
+            .as_ref()
+            .is_none_or(|title| title.is_match(&info.title))
+            && self.class.as_ref().is_none_or(|class| *class == info.class)
+            && self.process.as_ref().is_none_or(|process| {
+                info.process
+                    .as_ref()
+                    .is_some_and(|name| same_process(process, name))
+            })
+            && self.when.as_ref().is_none_or(|expr| expr.eval(info))
+    }
+}
+
+impl fmt::Display for WindowRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = vec![];
+        if let Some(title) = &self.title {
+            fields.push(format!("title={title}"));
+        }
+        if let Some(class) = &self.class {
+            fields.push(format!("class={class:?}"));
+        }
+        if let Some(process) = &self.process {
+            fields.push(format!("process={process:?}"));
+        }
+        if let Some(crop) = &self.crop {
+            fields.push(format!("crop={crop}"));
+        }
+        if !self.masks.is_empty() {
+            let names = self.masks.iter().map(|mask| mask.name.as_str());
+            fields.push(format!("masks={}", names.collect::<Vec<_>>().join(",")));
+        }
+        if let Some(expr) = &self.when {
+            fields.push(format!("when {expr}"));
+        }
+
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+impl TitlePattern {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern)
+            .map(Self)
+            .map_err(|e| format!("invalid title pattern: {e}"))
+    }
+
+    pub fn is_match(&self, title: &str) -> bool {
+        self.0.is_match(title)
+    }
+}
+
+impl fmt::Display for TitlePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}/", self.0)
+    }
+}
+
+impl Serialize for TitlePattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TitlePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::new(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window_id::{Backend, WindowId};
+
+    fn window(title: &str, class: &str, process: Option<&str>) -> WindowInfo {
+        WindowInfo {
+            id: WindowId::new(Backend::Mock, 1),
+            title: title.into(),
+            class: class.into(),
+            pid: Some(1),
+            process: process.map(Into::into),
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn parses_allow_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            [[allow]]
+            title = "Figma"
+
+            [[allow]]
+            class = "Code"
+            process = "code"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.allow.len(), 2);
+        assert_eq!(
+            config.judge(&window("design - Figma", "Chrome", None)),
+            Verdict::Allowed(1)
+        );
+        assert_eq!(
+            config.judge(&window("main.rs", "Code", Some("code"))),
+            Verdict::Allowed(2)
+        );
+        assert_eq!(
+            config.judge(&window("main.rs", "Code", Some("vim"))),
+            Verdict::Unmatched
+        );
+        assert_eq!(
+            config.judge(&window("Terminal", "Alacritty", None)),
+            Verdict::Unmatched
+        );
+    }
+
+    #[test]
+    fn deny_rules_override_allow_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            [[allow]]
+            when = 'title ~ /Figma/ || process == "code"'
+
+            [[deny]]
+            title = "Password"
+
+            [[deny]]
+            when = 'class in ["Private"]'
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.judge(&window("Design - Figma", "Chrome", None)),
+            Verdict::Allowed(1)
+        );
+        assert_eq!(
+            config.judge(&window("Password - Figma", "Chrome", None)),
+            Verdict::Denied(1)
+        );
+        assert_eq!(
+            config.judge(&window("main.rs", "Private", Some("code"))),
+            Verdict::Denied(2)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!(toml::from_str::<Config>("[[allow]]\ntitle = \"(\"").is_err());
+        assert!(toml::from_str::<Config>("[[allow]]\ntilte = \"Figma\"").is_err());
+
+        let err = toml::from_str::<Config>("[[deny]]\nwhen = 'title ~ Figma'").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("title ~ Figma\n        ^ unexpected `Figma`, expected /regex/"));
+    }
+
+    #[test]
+    fn empty_rule_matches_nothing() {
+        let rule = WindowRule::default();
+        assert!(!rule.matches(&window("", "", None)));
+    }
+
+    #[test]
+    fn process_names_ignore_case_and_extension() {
+        let rule = WindowRule {
+            process: Some("Code.exe".into()),
+            ..Default::default()
+        };
+        assert!(rule.matches(&window("", "", Some("code"))));
+        assert!(rule.matches(&window("", "", Some("CODE.EXE"))));
+        assert!(!rule.matches(&window("", "", Some("codex"))));
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let config = Config {
+            path: None,
+            allow: vec![WindowRule {
+                title: Some(TitlePattern::new("Inbox - .*").unwrap()),
+                class: None,
+                process: Some("thunderbird".into()),
+                when: Some("!(title ~ /personal/i)".parse().unwrap()),
+                crop: Some("client".parse().unwrap()),
+                masks: vec![Mask {
+                    name: "inbox".into(),
+                    region: "0,10%,200,50%".parse().unwrap(),
+                    style: "pixelate".parse().unwrap(),
+                }],
+            }],
+            deny: vec![],
+            focus: FocusConfig::default(),
+            placeholder: PlaceholderConfig {
+                hold_timeout_secs: Some(30.0),
+                ..Default::default()
+            },
+            transition: TransitionConfig::default(),
+            canvas: CanvasConfig::default(),
+            redact: RedactConfig {
+                titles: vec![TitlePattern::new("Private Browsing").unwrap()],
+                ..Default::default()
+            },
+            delay: DelayConfig {
+                secs: 3.0,
+                ..Default::default()
+            },
+            replay: ReplayConfig {
+                secs: 30.0,
+                max_mb: 1024,
+            },
+        };
+
+        let text = toml::to_string_pretty(&config).unwrap();
+        let loaded: Config = toml::from_str(&text).unwrap();
+        assert_eq!(loaded.allow[0].to_string(), config.allow[0].to_string());
+        assert_eq!(loaded.allow[0].masks, config.allow[0].masks);
+        assert_eq!(loaded.placeholder, config.placeholder);
+        assert_eq!(loaded.redact.to_string(), config.redact.to_string());
+        assert_eq!(loaded.delay, config.delay);
+        assert_eq!(loaded.replay, config.replay);
+        assert_eq!(
+            loaded.focus.ignore[0].to_string(),
+            config.focus.ignore[0].to_string()
+        );
+    }
+
+    #[test]
+    fn saves_by_replacing_the_file() {
+        let path = std::env::temp_dir().join(format!("config-test-{}.toml", std::process::id()));
+        fs::write(&path, "[[allow]]\nprocess = \"old\"\n").unwrap();
+
+        let mut config = Config::load(&path).unwrap();
+        config.allow[0].process = Some("new".into());
+        config.save().unwrap();
+
+        let loaded = Config::load(&path).unwrap();
+        assert_eq!(loaded.allow[0].process.as_deref(), Some("new"));
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        assert!(!Path::new(&tmp_path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
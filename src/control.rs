@@ -0,0 +1,315 @@
+//! シェル以外からの操作。ほかのプログラムやホットキーから、ソケットやシグナルで出力を消せるようにする。
+//!
+//! ソケットには 1 行に 1 つ `blackout [on|off]` を書き込む。返事は `ok` かエラーの 1 行。
+//! SIGUSR1 でブラックアウトし、SIGUSR2 で解除する。
+
+use std::{env, fmt, path::PathBuf};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+pub struct ControlServer {
+    rx_cmd: Receiver<ControlCommand>,
+    tx_msg: Sender<ControlMessage>,
+    // ソケットは UNIX でしか作らない
+    #[cfg_attr(not(unix), allow(dead_code))]
+    path: PathBuf,
+}
+
+pub enum ControlCommand {
+    Quit,
+}
+
+pub enum ControlMessage {
+    Blackout(BlackoutChange),
+    Output { message: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlackoutChange {
+    On,
+    Off,
+    Toggle,
+}
+
+impl ControlServer {
+    pub fn new(path: PathBuf) -> (Self, Sender<ControlCommand>, Receiver<ControlMessage>) {
+        let (tx_cmd, rx_cmd) = unbounded();
+        let (tx_msg, rx_msg) = unbounded();
+
+        (
+            Self {
+                rx_cmd,
+                tx_msg,
+                path,
+            },
+            tx_cmd,
+            rx_msg,
+        )
+    }
+
+    pub fn run(self) {
+        #[cfg(unix)]
+        let res = unix::run(&self);
+        #[cfg(not(unix))]
+        let res = {
+            let _ = self.rx_cmd.recv();
+            Ok::<_, String>(())
+        };
+
+        if let Err(e) = res {
+            let _ = self.tx_msg.send(ControlMessage::Output {
+                message: format!("control server stopped: {e}"),
+            });
+        }
+    }
+}
+
+/// 実行時ディレクトリがあればそこに、なければ一時ディレクトリに置く
+pub fn default_socket_path() -> PathBuf {
+    let dir = env::var_os("XDG_RUNTIME_DIR").map_or_else(env::temp_dir, PathBuf::from);
+    dir.join("obs-active-window-switcher.sock")
+}
+
+impl BlackoutChange {
+    /// 今の状態 blackout に当てはめた結果
+    pub fn apply(self, blackout: bool) -> bool {
+        match self {
+            BlackoutChange::On => true,
+            BlackoutChange::Off => false,
+            BlackoutChange::Toggle => !blackout,
+        }
+    }
+}
+
+/// `blackout [on|off]` の引数。省略すると切り替える
+pub fn parse_blackout(args: &[&str]) -> Result<BlackoutChange, String> {
+    match args {
+        [] => Ok(BlackoutChange::Toggle),
+        ["on"] => Ok(BlackoutChange::On),
+        ["off"] => Ok(BlackoutChange::Off),
+        _ => Err("usage: blackout [on|off]".into()),
+    }
+}
+
+impl fmt::Display for BlackoutChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BlackoutChange::On => "on",
+            BlackoutChange::Off => "off",
+            BlackoutChange::Toggle => "toggle",
+        })
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::{self, File},
+        io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+        mem,
+        os::unix::{
+            io::FromRawFd,
+            net::{UnixListener, UnixStream},
+        },
+        path::Path,
+        ptr,
+        sync::atomic::{AtomicI32, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use crossbeam_channel::{Sender, TryRecvError};
+
+    use super::{parse_blackout, BlackoutChange, ControlCommand, ControlMessage, ControlServer};
+
+    // シグナルハンドラーから読み手のスレッドへ番号を渡すパイプの書き込み側
+    static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    pub(super) fn run(server: &ControlServer) -> Result<(), String> {
+        watch_signals(server.tx_msg.clone()).map_err(|e| format!("signals: {e}"))?;
+
+        let listener = bind(&server.path).map_err(|e| format!("{}: {e}", server.path.display()))?;
+        // accept がブロックすると終了要求に気付けないので、ノンブロッキングにしてときどき見に行く
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        loop {
+            match server.rx_cmd.try_recv() {
+                Ok(ControlCommand::Quit) | Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let tx_msg = server.tx_msg.clone();
+                    thread::spawn(move || serve(stream, tx_msg));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+
+        let _ = fs::remove_file(&server.path);
+        Ok(())
+    }
+
+    /// 前回のソケットファイルが残っていたら、誰も待ち受けていないことを確かめてから消す
+    fn bind(path: &Path) -> io::Result<UnixListener> {
+        match UnixListener::bind(path) {
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(e);
+                }
+                fs::remove_file(path)?;
+                UnixListener::bind(path)
+            }
+            res => res,
+        }
+    }
+
+    fn serve(stream: UnixStream, tx_msg: Sender<ControlMessage>) {
+        let _ = stream.set_nonblocking(false);
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            let args: Vec<_> = line.split_whitespace().collect();
+            let reply = match args.split_first() {
+                None => continue,
+                Some((&"blackout", args)) => parse_blackout(args).map(ControlMessage::Blackout),
+                Some((command, _)) => Err(format!("unknown command: {command}")),
+            };
+
+            let reply = match reply {
+                Ok(msg) => match tx_msg.send(msg) {
+                    Ok(()) => "ok".to_owned(),
+                    Err(_) => "shutting down".to_owned(),
+                },
+                Err(e) => e,
+            };
+            if writeln!(writer, "{reply}").is_err() {
+                break;
+            }
+        }
+    }
+
+    extern "C" fn on_signal(signum: libc::c_int) {
+        // シグナルハンドラーの中ではパイプへの書き込みくらいしかできない
+        let byte = signum as u8;
+        let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+        unsafe { libc::write(fd, &byte as *const u8 as *const libc::c_void, 1) };
+    }
+
+    fn watch_signals(tx_msg: Sender<ControlMessage>) -> io::Result<()> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        SIGNAL_PIPE.store(fds[1], Ordering::Relaxed);
+
+        for signum in [libc::SIGUSR1, libc::SIGUSR2] {
+            let mut action: libc::sigaction = unsafe { mem::zeroed() };
+            action.sa_sigaction = on_signal as *const () as usize;
+            action.sa_flags = libc::SA_RESTART;
+            if unsafe { libc::sigaction(signum, &action, ptr::null_mut()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+        thread::spawn(move || {
+            let mut byte = [0];
+            while reader.read_exact(&mut byte).is_ok() {
+                let change = if byte[0] as libc::c_int == libc::SIGUSR1 {
+                    BlackoutChange::On
+                } else {
+                    BlackoutChange::Off
+                };
+                if tx_msg.send(ControlMessage::Blackout(change)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    fn blackout(msg: ControlMessage) -> Option<BlackoutChange> {
+        match msg {
+            ControlMessage::Blackout(change) => Some(change),
+            ControlMessage::Output { .. } => None,
+        }
+    }
+
+    #[test]
+    fn parses_blackout_args() {
+        assert_eq!(parse_blackout(&[]), Ok(BlackoutChange::Toggle));
+        assert_eq!(parse_blackout(&["off"]), Ok(BlackoutChange::Off));
+        assert!(parse_blackout(&["now"]).is_err());
+        assert!(BlackoutChange::Toggle.apply(false));
+        assert!(!BlackoutChange::Off.apply(true));
+    }
+
+    #[test]
+    fn accepts_commands_and_signals() {
+        let path = env::temp_dir().join(format!("control-test-{}.sock", std::process::id()));
+        let (server, tx_cmd, rx_msg) = ControlServer::new(path.clone());
+        let server = thread::spawn(move || server.run());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let stream = loop {
+            match UnixStream::connect(&path) {
+                Ok(stream) => break stream,
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Err(e) => panic!("failed to connect: {e}"),
+            }
+        };
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+
+        let mut reply = String::new();
+        writeln!(writer, "blackout on").unwrap();
+        reader.read_line(&mut reply).unwrap();
+        writeln!(writer, "quit").unwrap();
+        reader.read_line(&mut reply).unwrap();
+        assert_eq!(reply, "ok\nunknown command: quit\n");
+        assert_eq!(
+            rx_msg
+                .recv_timeout(Duration::from_secs(5))
+                .ok()
+                .and_then(blackout),
+            Some(BlackoutChange::On)
+        );
+
+        unsafe { libc::raise(libc::SIGUSR2) };
+        assert_eq!(
+            rx_msg
+                .recv_timeout(Duration::from_secs(5))
+                .ok()
+                .and_then(blackout),
+            Some(BlackoutChange::Off)
+        );
+
+        tx_cmd.send(ControlCommand::Quit).unwrap();
+        server.join().unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,229 @@
+//! ウィンドウの一部だけを出すための切り抜きと、その範囲の書き方。
+
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::window_capture::{CapturedFrame, Rect};
+
+/// `client` か、`<x>,<y>,<width>,<height>` の長方形
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Crop {
+    /// タイトルバーや枠を除いたクライアント領域
+    ClientArea,
+    Rect(Region),
+}
+
+/// ウィンドウの左上を原点とする `<x>,<y>,<width>,<height>` の長方形。各値は画素数か `10%` のような割合
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region([Length; 4]);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Length {
+    Pixels(u32),
+    Percent(f32),
+}
+
+impl Crop {
+    /// width x height のフレームの中で切り抜く範囲。はみ出した分は縮める
+    pub fn rect(&self, width: u32, height: u32, client_area: Option<Rect>) -> Rect {
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+
+        let rect = match self {
+            // クライアント領域が分からないときは、枠のないウィンドウを撮っているとみなす
+            Crop::ClientArea => client_area.and_then(|area| clip(area, width, height)),
+            Crop::Rect(region) => region.rect(width, height),
+        };
+
+        // 何も残らないなら切り抜かない
+        rect.unwrap_or(full)
+    }
+
+    pub fn apply(&self, frame: &CapturedFrame) -> CapturedFrame {
+        frame.crop(self.rect(frame.width, frame.height, frame.client_area))
+    }
+}
+
+impl Region {
+    /// width x height のフレームの中での範囲。はみ出した分は縮め、何も残らなければ None
+    pub fn rect(&self, width: u32, height: u32) -> Option<Rect> {
+        let [x, y, w, h] = self.0;
+        clip(
+            Rect {
+                x: x.resolve(width),
+                y: y.resolve(height),
+                width: w.resolve(width),
+                height: h.resolve(height),
+            },
+            width,
+            height,
+        )
+    }
+}
+
+fn clip(rect: Rect, width: u32, height: u32) -> Option<Rect> {
+    let x = rect.x.min(width);
+    let y = rect.y.min(height);
+    let rect = Rect {
+        x,
+        y,
+        width: rect.width.min(width - x),
+        height: rect.height.min(height - y),
+    };
+
+    (rect.width > 0 && rect.height > 0).then_some(rect)
+}
+
+impl Length {
+    fn resolve(self, total: u32) -> u32 {
+        match self {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(percent) => (total as f32 * percent / 100.0).round() as u32,
+        }
+    }
+}
+
+impl fmt::Display for Crop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Crop::ClientArea => f.write_str("client"),
+            Crop::Rect(region) => write!(f, "{region}"),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [x, y, w, h] = self.0;
+        write!(f, "{x},{y},{w},{h}")
+    }
+}
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Length::Pixels(pixels) => write!(f, "{pixels}"),
+            Length::Percent(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "client" {
+            return Ok(Crop::ClientArea);
+        }
+
+        s.parse().map(Crop::Rect)
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lengths = s
+            .split(',')
+            .map(|part| part.trim().parse())
+            .collect::<Result<Vec<Length>, _>>()?;
+        let Ok(lengths) = lengths.try_into() else {
+            return Err(format!(
+                "invalid region {s}, expected <x>,<y>,<width>,<height>"
+            ));
+        };
+
+        Ok(Region(lengths))
+    }
+}
+
+impl FromStr for Length {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let length = match s.strip_suffix('%') {
+            Some(percent) => percent
+                .parse()
+                .ok()
+                .filter(|percent| (0.0..=100.0).contains(percent))
+                .map(Length::Percent),
+            None => s.parse().ok().map(Length::Pixels),
+        };
+
+        length.ok_or_else(|| format!("invalid length {s}"))
+    }
+}
+
+impl Serialize for Crop {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Crop {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Region {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Region {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn resolves_pixels_and_percentages() {
+        let crop: Crop = "10,10%,50%,100".parse().unwrap();
+        assert_eq!(crop.rect(200, 100, None), rect(10, 10, 100, 90));
+        assert_eq!(crop.to_string(), "10,10%,50%,100");
+    }
+
+    #[test]
+    fn client_area_falls_back_to_whole_frame() {
+        let area = rect(8, 30, 100, 50);
+        assert_eq!(Crop::ClientArea.rect(116, 88, Some(area)), area);
+        assert_eq!(Crop::ClientArea.rect(116, 88, None), rect(0, 0, 116, 88));
+    }
+
+    #[test]
+    fn empty_crop_keeps_whole_frame() {
+        let crop: Crop = "300,0,10,10".parse().unwrap();
+        assert_eq!(crop.rect(200, 100, None), rect(0, 0, 200, 100));
+    }
+
+    #[test]
+    fn rejects_malformed_crops() {
+        assert!("client-area".parse::<Crop>().is_err());
+        assert!("1,2,3".parse::<Crop>().is_err());
+        assert!("0,0,150%,10".parse::<Crop>().is_err());
+        assert!("0,0,-1,10".parse::<Crop>().is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! 配信の遅延。出力を数秒遅らせて、映ってはいけないものが出る前にブラックアウトできるようにする。
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{window_capture::CapturedFrame, window_id::WindowId};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DelayConfig {
+    // 0 なら遅らせない
+    pub secs: f64,
+    // 溜めておくフレームの合計の上限。超えたら古いものから捨てる
+    pub max_mb: u64,
+}
+
+impl Default for DelayConfig {
+    fn default() -> Self {
+        Self {
+            secs: 0.0,
+            max_mb: 512,
+        }
+    }
+}
+
+impl DelayConfig {
+    // 時刻に足してもあふれないよう、設定ファイルに大きな値が書かれていてもここまでにする
+    pub const MAX_SECS: u64 = 3600;
+
+    pub fn delay(&self) -> Duration {
+        Duration::try_from_secs_f64(self.secs)
+            .unwrap_or_default()
+            .min(Duration::from_secs(Self::MAX_SECS))
+    }
+
+    fn max_bytes(&self) -> usize {
+        (self.max_mb as usize).saturating_mul(1024 * 1024)
+    }
+}
+
+impl fmt::Display for DelayConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.delay().is_zero() {
+            return f.write_str("off");
+        }
+
+        write!(f, "{}s (up to {} MB)", self.secs, self.max_mb)
+    }
+}
+
+/// 出す時刻の付いたフレームの列。時刻は呼び出し側が渡す。
+#[derive(Default)]
+pub struct DelayLine {
+    queue: VecDeque<(Instant, CapturedFrame)>,
+    bytes: usize,
+    // 上限を超えて捨てたフレームの数
+    dropped: u64,
+}
+
+impl DelayLine {
+    pub fn push(&mut self, frame: CapturedFrame, config: &DelayConfig, now: Instant) {
+        self.bytes += frame.bytes.len();
+        self.queue.push_back((now + config.delay(), frame));
+
+        // 最新のフレームだけは残す
+        while self.bytes > config.max_bytes() && self.queue.len() > 1 {
+            self.pop_front();
+            self.dropped += 1;
+        }
+    }
+
+    /// 次のフレームを出す時刻
+    pub fn deadline(&self) -> Option<Instant> {
+        self.queue.front().map(|(due, _)| *due)
+    }
+
+    /// 時刻になったフレームのうち最新のもの。それより前のものは出しても一瞬で上書きされるので捨てる
+    pub fn poll(&mut self, now: Instant) -> Option<CapturedFrame> {
+        let mut latest = None;
+        while self.deadline().is_some_and(|due| due <= now) {
+            latest = self.pop_front();
+        }
+        latest
+    }
+
+    /// まだ出していないフレームをすべて捨て、その数を返す
+    pub fn dump(&mut self) -> usize {
+        let count = self.queue.len();
+        self.queue.clear();
+        self.bytes = 0;
+        count
+    }
+
+    /// 出してはいけなくなったウィンドウのフレームだけを捨て、その数を返す
+    pub fn drop_window(&mut self, id: WindowId) -> usize {
+        let count = self.queue.len();
+        self.queue.retain(|(_, frame)| frame.id != id);
+        self.bytes = self.queue.iter().map(|(_, frame)| frame.bytes.len()).sum();
+        count - self.queue.len()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn pop_front(&mut self) -> Option<CapturedFrame> {
+        let (_, frame) = self.queue.pop_front()?;
+        self.bytes -= frame.bytes.len();
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::mock_window, window_capture::PixelFormat};
+
+    fn frame(n: u8, len: usize) -> CapturedFrame {
+        CapturedFrame {
+            id: mock_window(n.into()),
+            width: 1,
+            height: 1,
+            stride: 4,
+            format: PixelFormat::Rgba8,
+            timestamp: Instant::now(),
+            bytes: vec![n; len].into(),
+            client_area: None,
+        }
+    }
+
+    fn config(secs: f64, max_mb: u64) -> DelayConfig {
+        DelayConfig { secs, max_mb }
+    }
+
+    #[test]
+    fn emits_frames_after_delay() {
+        let config = config(2.0, 1);
+        let now = Instant::now();
+        let mut line = DelayLine::default();
+        line.push(frame(1, 4), &config, now);
+        line.push(frame(2, 4), &config, now + Duration::from_millis(100));
+
+        assert!(line.poll(now + Duration::from_secs(1)).is_none());
+        assert_eq!(line.deadline(), Some(now + Duration::from_secs(2)));
+        assert_eq!(line.poll(now + Duration::from_secs(2)).unwrap().bytes[0], 1);
+        // 遅れて呼ばれたら溜まっていたうちの最新だけを出す
+        line.push(frame(3, 4), &config, now + Duration::from_millis(200));
+        assert_eq!(line.poll(now + Duration::from_secs(3)).unwrap().bytes[0], 3);
+        assert_eq!(line.queued(), 0);
+    }
+
+    #[test]
+    fn caps_huge_delays() {
+        let config = config(1e19, 1);
+        assert_eq!(config.delay(), Duration::from_secs(DelayConfig::MAX_SECS));
+
+        let now = Instant::now();
+        let mut line = DelayLine::default();
+        line.push(frame(1, 4), &config, now);
+        assert!(line.poll(now).is_none());
+    }
+
+    #[test]
+    fn passes_frames_through_without_delay() {
+        let now = Instant::now();
+        let mut line = DelayLine::default();
+        line.push(frame(1, 4), &DelayConfig::default(), now);
+
+        assert_eq!(line.poll(now).unwrap().bytes[0], 1);
+    }
+
+    #[test]
+    fn drops_oldest_frames_over_limit() {
+        let config = config(5.0, 1);
+        let now = Instant::now();
+        let mut line = DelayLine::default();
+        for n in 0..5 {
+            line.push(frame(n, 400 * 1024), &config, now);
+        }
+
+        assert_eq!((line.queued(), line.dropped()), (2, 3));
+        assert_eq!(line.dump(), 2);
+        assert!(line.poll(now + Duration::from_secs(5)).is_none());
+    }
+
+    #[test]
+    fn drops_frames_of_one_window() {
+        let config = config(2.0, 1);
+        let now = Instant::now();
+        let mut line = DelayLine::default();
+        for n in [1, 2, 1, 2] {
+            line.push(frame(n, 4), &config, now);
+        }
+
+        assert_eq!(line.drop_window(mock_window(2)), 2);
+        assert_eq!(line.drop_window(mock_window(2)), 0);
+        assert_eq!(line.queued(), 2);
+        assert_eq!(line.poll(now + Duration::from_secs(2)).unwrap().bytes[0], 1);
+    }
+}
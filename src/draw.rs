@@ -0,0 +1,336 @@
+//! RGBA8 のバッファに直接描き込むための簡単な描画ルーチン。
+
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// 文字幅と字間を合わせた 1 文字分の送り幅 (scale = 1 のとき)
+pub const ADVANCE: u32 = GLYPH_WIDTH + 1;
+pub const LINE_HEIGHT: u32 = GLYPH_HEIGHT;
+
+/// 設定ファイルなどで `#rrggbb` と書く不透明な色
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color(pub [u8; 4]);
+
+impl Color {
+    pub const BLACK: Color = Color([0, 0, 0, 255]);
+    pub const WHITE: Color = Color([255, 255, 255, 255]);
+
+    /// この色の上に文字を書くときに読みやすい色
+    pub fn contrasting(self) -> Color {
+        let [r, g, b, _] = self.0.map(u32::from);
+        if r * 299 + g * 587 + b * 114 > 128 * 1000 {
+            Color::BLACK
+        } else {
+            Color::WHITE
+        }
+    }
+}
+
+pub fn fill_rect(
+    bytes: &mut [u8],
+    width: u32,
+    height: u32,
+    (x, y, w, h): (u32, u32, u32, u32),
+    color: [u8; 4],
+) {
+    let x_end = (x + w).min(width);
+    let y_end = (y + h).min(height);
+    for py in y.min(height)..y_end {
+        let row = (py * width) as usize * 4;
+        for px in x.min(width)..x_end {
+            let i = row + px as usize * 4;
+            bytes[i..i + 4].copy_from_slice(&color);
+        }
+    }
+}
+
+pub fn text_width(text: &str, scale: u32) -> u32 {
+    (text.chars().count() as u32 * ADVANCE).saturating_sub(1) * scale
+}
+
+/// 5x7 のビットマップフォントで文字列を描く。知らない文字は空白になる。
+pub fn draw_text(
+    bytes: &mut [u8],
+    width: u32,
+    height: u32,
+    (x, y): (u32, u32),
+    scale: u32,
+    text: &str,
+    color: [u8; 4],
+) {
+    for (i, ch) in text.chars().enumerate() {
+        let gx = x + i as u32 * ADVANCE * scale;
+        for (row, bits) in glyph(ch).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+
+                fill_rect(
+                    bytes,
+                    width,
+                    height,
+                    (gx + col * scale, y + row as u32 * scale, scale, scale),
+                    color,
+                );
+            }
+        }
+    }
+}
+
+/// 半径 radius の箱型のぼかしを 3 回かけて、ガウスぼかしに近づける
+pub fn blur(bytes: &mut [u8], width: u32, height: u32, radius: u32) {
+    if radius == 0 || width == 0 || height == 0 {
+        return;
+    }
+
+    let (w, h, r) = (width as usize, height as usize, radius as usize);
+    let mut line = vec![0; w.max(h) * 4];
+    let mut blurred = line.clone();
+    for _ in 0..3 {
+        for row in bytes.chunks_exact_mut(w * 4).take(h) {
+            line[..w * 4].copy_from_slice(row);
+            blur_line(&line[..w * 4], row, r);
+        }
+
+        for x in 0..w {
+            for y in 0..h {
+                let i = (y * w + x) * 4;
+                line[y * 4..y * 4 + 4].copy_from_slice(&bytes[i..i + 4]);
+            }
+            blur_line(&line[..h * 4], &mut blurred[..h * 4], r);
+            for y in 0..h {
+                let i = (y * w + x) * 4;
+                bytes[i..i + 4].copy_from_slice(&blurred[y * 4..y * 4 + 4]);
+            }
+        }
+    }
+}
+
+/// 長方形の中だけをぼかす。外側の画素は混ぜない
+pub fn blur_rect(
+    bytes: &mut [u8],
+    width: u32,
+    height: u32,
+    (x, y, w, h): (u32, u32, u32, u32),
+    radius: u32,
+) {
+    let (x_end, y_end) = ((x + w).min(width), (y + h).min(height));
+    if x >= x_end || y >= y_end {
+        return;
+    }
+
+    let row_len = (x_end - x) as usize * 4;
+    let rows = |y: u32| (y * width + x) as usize * 4;
+    let mut region = Vec::with_capacity(row_len * (y_end - y) as usize);
+    for py in y..y_end {
+        region.extend_from_slice(&bytes[rows(py)..rows(py) + row_len]);
+    }
+
+    blur(&mut region, x_end - x, y_end - y, radius);
+
+    for (py, src) in (y..y_end).zip(region.chunks_exact(row_len)) {
+        bytes[rows(py)..rows(py) + row_len].copy_from_slice(src);
+    }
+}
+
+/// 長方形の中を block 画素四方ごとの平均色で塗りつぶす
+pub fn pixelate(
+    bytes: &mut [u8],
+    width: u32,
+    height: u32,
+    (x, y, w, h): (u32, u32, u32, u32),
+    block: u32,
+) {
+    let (x_end, y_end) = ((x + w).min(width), (y + h).min(height));
+    let block = block.max(1);
+    for by in (y..y_end).step_by(block as usize) {
+        for bx in (x..x_end).step_by(block as usize) {
+            let cell = (bx, by, block.min(x_end - bx), block.min(y_end - by));
+
+            let mut sum = [0u64; 4];
+            for py in cell.1..cell.1 + cell.3 {
+                for px in cell.0..cell.0 + cell.2 {
+                    let i = (py * width + px) as usize * 4;
+                    for (sum, &value) in sum.iter_mut().zip(&bytes[i..i + 4]) {
+                        *sum += value as u64;
+                    }
+                }
+            }
+
+            let count = (cell.2 * cell.3) as u64;
+            fill_rect(
+                bytes,
+                width,
+                height,
+                cell,
+                sum.map(|sum| (sum / count) as u8),
+            );
+        }
+    }
+}
+
+/// 端の画素が外側まで続いているものとして、前後 radius 画素の平均をとる
+fn blur_line(src: &[u8], dst: &mut [u8], radius: usize) {
+    let last = src.len() / 4 - 1;
+    let window = (radius * 2 + 1) as u32;
+    for c in 0..4 {
+        let at = |i: usize| src[i.min(last) * 4 + c] as u32;
+        let mut sum = at(0) * radius as u32 + (0..=radius).map(at).sum::<u32>();
+        for i in 0..=last {
+            dst[i * 4 + c] = (sum / window) as u8;
+            sum += at(i + radius + 1);
+            sum -= at(i.saturating_sub(radius));
+        }
+    }
+}
+
+fn glyph(ch: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match ch.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '/' => [0x01, 0x02, 0x02, 0x04, 0x08, 0x08, 0x10],
+        '@' => [0x0E, 0x11, 0x17, 0x15, 0x17, 0x10, 0x0E],
+        _ => [0; GLYPH_HEIGHT as usize],
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b, _] = self.0;
+        write!(f, "#{r:02x}{g:02x}{b:02x}")
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.is_ascii())
+            .ok_or_else(|| format!("invalid color {s}, expected #rrggbb"))?;
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("invalid color {s}, expected #rrggbb"))
+        };
+
+        Ok(Color([channel(0)?, channel(2)?, channel(4)?, 255]))
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_rect_is_clipped_to_buffer() {
+        let mut bytes = vec![0; 4 * 4 * 4];
+        fill_rect(&mut bytes, 4, 4, (2, 2, 10, 10), [1, 2, 3, 4]);
+
+        let filled = bytes
+            .chunks_exact(4)
+            .filter(|px| px == &[1, 2, 3, 4])
+            .count();
+        assert_eq!(filled, 4);
+    }
+
+    #[test]
+    fn draws_glyph_pixels() {
+        let mut bytes = vec![0; 12 * 7 * 4];
+        draw_text(&mut bytes, 12, 7, (0, 0), 1, "-1", [255; 4]);
+
+        // '-' は 4 行目だけが横一線に埋まる
+        let row3 = &bytes[(3 * 12) * 4..(3 * 12 + 5) * 4];
+        assert!(row3.iter().all(|&b| b == 255));
+        let row0 = &bytes[0..5 * 4];
+        assert!(row0.iter().all(|&b| b == 0));
+        assert_eq!(text_width("-1", 1), 11);
+    }
+
+    #[test]
+    fn blur_spreads_bright_pixel_and_keeps_flat_areas() {
+        let mut bytes = [0, 0, 0, 255].repeat(9 * 9);
+        bytes[(4 * 9 + 4) * 4..(4 * 9 + 4) * 4 + 3].copy_from_slice(&[255, 255, 255]);
+        blur(&mut bytes, 9, 9, 1);
+
+        let at = |x: usize, y: usize| bytes[(y * 9 + x) * 4];
+        assert!(at(4, 4) < 255);
+        assert!(at(3, 4) > 0 && at(4, 5) > 0);
+        assert_eq!(at(0, 0), 0);
+        assert!(bytes.chunks_exact(4).all(|px| px[3] == 255));
+    }
+
+    #[test]
+    fn parses_hex_colors() {
+        let color: Color = "#00ff80".parse().unwrap();
+        assert_eq!(color, Color([0, 255, 128, 255]));
+        assert_eq!(color.to_string(), "#00ff80");
+        assert!("00ff80".parse::<Color>().is_err());
+        assert!("#00ff8".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn pixelate_averages_blocks_inside_rect() {
+        // 4x2 の左の 2x2 だけをひとつのブロックにする
+        let mut bytes = [[0, 0, 0, 255], [200, 200, 200, 255]].concat().repeat(4);
+        pixelate(&mut bytes, 4, 2, (0, 0, 2, 2), 2);
+
+        let red: Vec<_> = bytes.chunks_exact(4).map(|px| px[0]).collect();
+        assert_eq!(red, [100, 100, 0, 200, 100, 100, 0, 200]);
+    }
+}
@@ -1,24 +1,61 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fmt::Write,
+    fmt::{self, Write},
+    path::PathBuf,
     thread::{self, JoinHandle},
+    time::Instant,
 };
 
-use crossbeam_channel::{bounded, Receiver, Sender};
-use windows::Win32::Foundation::HWND;
+use crossbeam_channel::{never, Receiver, Select, Sender, TryRecvError};
 
 use crate::{
+    canvas::{CanvasChange, OutputCanvas},
+    config::{Config, RuleKind, Verdict, WindowRule},
+    control::{BlackoutChange, ControlCommand, ControlMessage},
+    crop::Crop,
+    delay_line::DelayLine,
+    focus_filter::FocusDebouncer,
     foreground_watcher::{ForegroundWatcherCommand, ForegroundWatcherMessage},
+    frame_mailbox::{frame_mailbox, FrameMailbox},
     image_viewer::{ImageViewerCommand, ImageViewerMessage},
-    stdin_shell::{StdinShellCommand, StdinShellMessage},
-    window_capture::{CapturedFrame, WindowCapture, WindowCaptureCommand, WindowCaptureMessage},
+    mask::{apply_masks, Mask},
+    placeholder::{blackout_frame, PlaceholderChange, PlaceholderPolicy},
+    redact::RedactChange,
+    replay::ReplayBuffer,
+    rule_expr::RuleExpr,
+    stdin_shell::{ScanEntry, StdinShellCommand, StdinShellMessage},
+    transition::{TransitionKind, TransitionStage},
+    window_capture::{
+        CaptureBackend, CaptureBackendFactory, CapturedFrame, TestPatternBackend,
+        TestPatternBackendFactory, TestPatternConfig, WindowCapture, WindowCaptureCommand,
+        WindowCaptureMessage,
+    },
+    window_id::{Backend, WindowId},
+    window_list::WindowInfo,
 };
 
 struct WindowCaptureInterop {
-    _tx_cmd: Sender<WindowCaptureCommand>,
+    tx_cmd: Sender<WindowCaptureCommand>,
     rx_msg: Receiver<WindowCaptureMessage>,
-    rx_frame: Receiver<CapturedFrame>,
+    frames: FrameMailbox,
     thread: JoinHandle<()>,
+    // 最後にフォーカスされたときのウィンドウの情報と、表示してよいとした理由。テストパターンにはない
+    info: Option<WindowInfo>,
+    admission: Option<Admission>,
+}
+
+/// ウィンドウを表示してよいかと、それを決めたもの
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Admission {
+    // allow コマンドで個別に許可されている
+    Allowed,
+    AllowedByProcess(u32),
+    AllowedByOwner(WindowId),
+    AllowedByRule(usize),
+    // deny コマンドで個別に拒否されている
+    Denied,
+    DeniedByRule(usize),
+    NotAllowed,
 }
 
 pub struct Driver {
@@ -28,10 +65,50 @@ pub struct Driver {
     fw_rx_msg: Receiver<ForegroundWatcherMessage>,
     sh_tx_cmd: Sender<StdinShellCommand>,
     sh_rx_msg: Receiver<StdinShellMessage>,
+    ctl_tx_cmd: Option<Sender<ControlCommand>>,
+    ctl_rx_msg: Receiver<ControlMessage>,
+    capture_backend: CaptureBackendFactory,
+    test_pattern_backend: TestPatternBackendFactory,
+    config: Config,
+    // 今のターンの時刻。時間待ちするものにはすべてこれを渡す
+    now: Instant,
 
-    caps: BTreeMap<isize, WindowCaptureInterop>,
-    allowed_hwnds: BTreeSet<isize>,
-    current_hwnd: Option<HWND>,
+    caps: BTreeMap<WindowId, WindowCaptureInterop>,
+    // 止めるよう伝えたがまだスレッドが終わっていないキャプチャ。同じウィンドウを出し直すときは
+    // 別に新しく始める
+    stopping: Vec<(WindowId, WindowCaptureInterop)>,
+    allowed_windows: BTreeSet<WindowId>,
+    // PID が使い回されたときに別のプロセスを許可しないよう、プロセス名も覚えておく
+    allowed_processes: BTreeMap<u32, Option<String>>,
+    allowed_owners: BTreeSet<WindowId>,
+    denied_windows: BTreeSet<WindowId>,
+    // 許可ルールに当てはまらず、設定ファイルに保存できなかった切り抜き
+    crops: BTreeMap<WindowId, Crop>,
+    // 許可ルールに保存できなかった隠す範囲
+    masks: BTreeMap<WindowId, Vec<Mask>>,
+    current_window: Option<WindowId>,
+    // 前面に来てすぐ離れたウィンドウには切り替えない
+    focus: FocusDebouncer,
+    // 最後にフォーカスされたウィンドウ。許可されていないものも含む
+    focused_window: Option<WindowInfo>,
+    // ピン留めされている間はフォーカスに関係なくそのウィンドウを出す
+    pinned_window: Option<WindowId>,
+    follows_focus: bool,
+    // 最後にビューアーに送ったウィンドウのフレーム。代わりの画面を作るのに使う
+    last_frame: Option<CapturedFrame>,
+    // 出してよいウィンドウがないので、代わりの画面を出している
+    shows_placeholder: bool,
+    // hold からスレートに切り替える時刻
+    slate_deadline: Option<Instant>,
+    canvas: OutputCanvas,
+    transition: TransitionStage,
+    // ビューアーに送るまで遅らせるフレーム
+    delay: DelayLine,
+    // ビューアーに送ったフレーム
+    replay: ReplayBuffer,
+    // 解除されるまで何も出さない
+    blackout: bool,
+    next_test_pattern: u64,
     is_running: bool,
 }
 
@@ -43,6 +120,7 @@ impl Driver {
         fw_rx_msg: Receiver<ForegroundWatcherMessage>,
         sh_tx_cmd: Sender<StdinShellCommand>,
         sh_rx_msg: Receiver<StdinShellMessage>,
+        capture_backend: CaptureBackendFactory,
     ) -> Self {
         Self {
             im_tx_cmd,
@@ -51,34 +129,179 @@ impl Driver {
             fw_rx_msg,
             sh_tx_cmd,
             sh_rx_msg,
+            ctl_tx_cmd: None,
+            ctl_rx_msg: never(),
+            capture_backend,
+            test_pattern_backend: Box::new(|config| Box::new(TestPatternBackend::new(config))),
+            config: Config::default(),
+            now: Instant::now(),
 
             caps: BTreeMap::new(),
-            allowed_hwnds: BTreeSet::new(),
-            current_hwnd: None,
+            stopping: vec![],
+            allowed_windows: BTreeSet::new(),
+            allowed_processes: BTreeMap::new(),
+            allowed_owners: BTreeSet::new(),
+            denied_windows: BTreeSet::new(),
+            crops: BTreeMap::new(),
+            masks: BTreeMap::new(),
+            current_window: None,
+            focus: FocusDebouncer::default(),
+            focused_window: None,
+            pinned_window: None,
+            follows_focus: true,
+            last_frame: None,
+            shows_placeholder: false,
+            slate_deadline: None,
+            canvas: OutputCanvas::default(),
+            transition: TransitionStage::default(),
+            delay: DelayLine::default(),
+            replay: ReplayBuffer::default(),
+            blackout: false,
+            next_test_pattern: 1,
             is_running: false,
         }
     }
 
+    /// 保存されている許可ルールなどを使う
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// ソケットやシグナルからも操作できるようにする
+    pub fn with_control(
+        mut self,
+        ctl_tx_cmd: Sender<ControlCommand>,
+        ctl_rx_msg: Receiver<ControlMessage>,
+    ) -> Self {
+        self.ctl_tx_cmd = Some(ctl_tx_cmd);
+        self.ctl_rx_msg = ctl_rx_msg;
+        self
+    }
+
+    /// テストパターンを別の実装で作る
+    #[cfg(test)]
+    pub fn with_test_pattern_backend(mut self, factory: TestPatternBackendFactory) -> Self {
+        self.test_pattern_backend = factory;
+        self
+    }
+
     pub fn run(mut self) {
         self.is_running = true;
         while self.is_running {
-            if let Ok(msg) = self.im_rx_msg.try_recv() {
-                self.handle_image_viewer_message(msg);
-            }
+            self.wait_for_events();
+            self.turn(Instant::now());
+        }
+    }
+
+    /// 届いているものをすべて処理し、now までに時刻になったものを進める
+    fn turn(&mut self, now: Instant) {
+        self.now = now;
+
+        self.handle_image_viewer_messages();
+
+        self.handle_foreground_watcher_messages();
+
+        self.handle_stdin_shell_messages();
 
-            if let Ok(msg) = self.fw_rx_msg.try_recv() {
-                self.handle_foreground_watcher_message(msg);
+        self.handle_control_messages();
+
+        self.handle_captures_message();
+
+        self.handle_captures_frames();
+
+        self.handle_deadlines();
+    }
+
+    /// いずれかのチャンネルに何か届くか、時間待ちしているものの時刻になるまで眠る
+    fn wait_for_events(&self) {
+        let mut select = Select::new();
+        select.recv(&self.im_rx_msg);
+        select.recv(&self.fw_rx_msg);
+        select.recv(&self.sh_rx_msg);
+        select.recv(&self.ctl_rx_msg);
+        for cap in self.caps.values() {
+            select.recv(&cap.rx_msg);
+            select.recv(cap.frames.ready());
+        }
+        for (_, cap) in &self.stopping {
+            select.recv(&cap.rx_msg);
+        }
+
+        match [
+            self.slate_deadline,
+            self.focus.deadline(),
+            self.transition.deadline(),
+            self.delay.deadline(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        {
+            Some(deadline) => {
+                let _ = select.ready_deadline(deadline);
             }
+            None => {
+                select.ready();
+            }
+        }
+    }
 
-            if let Ok(msg) = self.sh_rx_msg.try_recv() {
-                self.handle_stdin_shell_message(msg);
+    fn handle_image_viewer_messages(&mut self) {
+        loop {
+            match self.im_rx_msg.try_recv() {
+                Ok(msg) => self.handle_image_viewer_message(msg),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // ビューアーがいなくなったら続ける意味がない
+                    self.im_rx_msg = never();
+                    self.quit();
+                    break;
+                }
             }
+        }
+    }
 
-            self.handle_captures_message();
+    fn handle_foreground_watcher_messages(&mut self) {
+        loop {
+            match self.fw_rx_msg.try_recv() {
+                Ok(msg) => self.handle_foreground_watcher_message(msg),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // 切断されたチャンネルは常に ready になってしまうので、二度と待たないようにする
+                    self.fw_rx_msg = never();
+                    break;
+                }
+            }
+        }
+    }
 
-            self.handle_captures_frames();
+    fn handle_stdin_shell_messages(&mut self) {
+        loop {
+            match self.sh_rx_msg.try_recv() {
+                Ok(msg) => self.handle_stdin_shell_message(msg),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.sh_rx_msg = never();
+                    break;
+                }
+            }
+        }
+    }
 
-            self.cleanup_threads();
+    fn handle_control_messages(&mut self) {
+        loop {
+            match self.ctl_rx_msg.try_recv() {
+                Ok(ControlMessage::Blackout(change)) => self.change_blackout(change),
+                Ok(ControlMessage::Output { message }) => {
+                    let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.ctl_rx_msg = never();
+                    break;
+                }
+            }
         }
     }
 
@@ -90,112 +313,2162 @@ impl Driver {
 
     fn handle_foreground_watcher_message(&mut self, msg: ForegroundWatcherMessage) {
         match msg {
-            ForegroundWatcherMessage::WindowChanged { hwnd } => {
-                if self.allowed_hwnds.contains(&hwnd.0) {
-                    self.current_hwnd = Some(hwnd);
-                    if !self.caps.contains_key(&hwnd.0) {
-                        self.start_capture_for(hwnd);
-                    }
-                } else {
-                    let hwnd_id = hwnd.0;
-                    let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
-                        message: format!("[{hwnd_id}] not allowed"),
-                    });
+            ForegroundWatcherMessage::WindowChanged { info } => {
+                if self.config.focus.ignores(&info) {
+                    return;
+                }
+
+                let dwell = self.config.focus.dwell();
+                if let Some(info) = self.focus.on_focus(info, dwell, self.now) {
+                    self.focus_settled(info);
                 }
             }
+            ForegroundWatcherMessage::Output { message } => {
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
+            }
         }
     }
 
     fn handle_stdin_shell_message(&mut self, msg: StdinShellMessage) {
         match msg {
             StdinShellMessage::QuitRequested => self.quit(),
-            StdinShellMessage::AllowHWND(hwnds) => {
-                self.allowed_hwnds.extend(hwnds.iter().map(|hwnd| hwnd.0))
-            }
+            StdinShellMessage::Allow(ids) => self.allowed_windows.extend(ids),
+            StdinShellMessage::Revoke(windows) => self.revoke(windows),
+            StdinShellMessage::Deny(ids) => self.deny(ids),
+            StdinShellMessage::Clear => self.clear(),
+            StdinShellMessage::Pin(info) => self.pin(info),
+            StdinShellMessage::Unpin => self.unpin(),
+            StdinShellMessage::Follow(follows) => self.follow(follows),
+            StdinShellMessage::AllowProcess(windows) => self.allow_processes(windows),
+            StdinShellMessage::AllowOwner(ids) => self.allowed_owners.extend(ids),
+            StdinShellMessage::AddRule(kind, rule) => self.add_rule(kind, rule),
+            StdinShellMessage::RemoveRule(kind, index) => self.remove_rule(kind, index),
+            StdinShellMessage::TestRules { expr, windows } => self.test_rules(expr, &windows),
+            StdinShellMessage::Placeholder(change) => self.change_placeholder(change),
+            StdinShellMessage::Dwell(dwell_ms) => self.change_dwell(dwell_ms),
+            StdinShellMessage::Transition(config) => self.change_transition(config),
+            StdinShellMessage::Canvas(changes) => self.change_canvas(changes),
+            StdinShellMessage::Crop(info, crop) => self.set_crop(info, crop),
+            StdinShellMessage::Redact(change) => self.change_redact(change),
+            StdinShellMessage::Blackout(change) => self.change_blackout(change),
+            StdinShellMessage::Delay(delay_secs) => self.change_delay(delay_secs),
+            StdinShellMessage::Replay(replay_secs) => self.change_replay(replay_secs),
+            StdinShellMessage::SaveReplay(path) => self.save_replay(path),
+            StdinShellMessage::AddMask(info, mask) => self.add_mask(info, mask),
+            StdinShellMessage::RemoveMask(info, name) => self.remove_mask(info, &name),
+            StdinShellMessage::ListMasks(info) => self.list_masks(info.as_ref()),
             StdinShellMessage::ListRequested => {
                 let mut buf = String::new();
-                writeln!(buf, "Got allowed HWNDs:").unwrap();
-                for hwnd_id in &self.allowed_hwnds {
-                    writeln!(buf, "| {}", hwnd_id).unwrap();
+                writeln!(buf, "Got allowed windows:").unwrap();
+                for id in &self.allowed_windows {
+                    writeln!(buf, "| {}", id).unwrap();
+                }
+                writeln!(buf, "Allowed processes:").unwrap();
+                for (pid, process) in &self.allowed_processes {
+                    writeln!(buf, "| {} ({})", pid, process.as_deref().unwrap_or("?")).unwrap();
+                }
+                writeln!(buf, "Allowed owners:").unwrap();
+                for id in &self.allowed_owners {
+                    writeln!(buf, "| {}", id).unwrap();
+                }
+                writeln!(buf, "Denied windows:").unwrap();
+                for id in &self.denied_windows {
+                    writeln!(buf, "| {}", id).unwrap();
+                }
+                for (kind, title) in [(RuleKind::Allow, "Allow"), (RuleKind::Deny, "Deny")] {
+                    writeln!(buf, "{title} rules:").unwrap();
+                    for (i, rule) in self.config.rules(kind).iter().enumerate() {
+                        writeln!(buf, "| #{} {}", i + 1, rule).unwrap();
+                    }
+                }
+                writeln!(buf, "Capturing windows:").unwrap();
+                for (id, cap) in &self.caps {
+                    let stats = cap.frames.stats();
+                    write!(
+                        buf,
+                        "| {} ({} frames, {} dropped",
+                        id, stats.posted, stats.dropped
+                    )
+                    .unwrap();
+                    match cap.admission {
+                        Some(admission) => writeln!(buf, ", {admission})").unwrap(),
+                        None => writeln!(buf, ")").unwrap(),
+                    }
                 }
 
                 let _ = self
                     .sh_tx_cmd
                     .send(StdinShellCommand::Output { message: buf });
             }
+            StdinShellMessage::ShowTestPattern(config) => self.show_test_pattern(config),
         }
     }
 
-    fn handle_captures_message(&mut self) {
-        let mut to_remove = vec![];
-        for WindowCaptureInterop { rx_msg, .. } in self.caps.values_mut() {
-            if let Ok(msg) = rx_msg.try_recv() {
-                match msg {
-                    WindowCaptureMessage::Closed { hwnd } => {
-                        to_remove.push(hwnd);
-                    }
-                    WindowCaptureMessage::Output { message } => {
-                        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
-                    }
-                }
+    /// 拒否は allow コマンドでの個別の許可よりも優先する
+    fn admission(&self, info: &WindowInfo) -> Admission {
+        if self.denied_windows.contains(&info.id) {
+            return Admission::Denied;
+        }
+
+        let verdict = self.config.judge(info);
+        if let Verdict::Denied(i) = verdict {
+            return Admission::DeniedByRule(i);
+        }
+
+        if self.allowed_windows.contains(&info.id) || self.allowed_owners.contains(&info.id) {
+            return Admission::Allowed;
+        }
+
+        if let Some(owner) = info
+            .owner
+            .filter(|owner| self.allowed_owners.contains(owner))
+        {
+            return Admission::AllowedByOwner(owner);
+        }
+
+        if let Some(pid) = info
+            .pid
+            .filter(|pid| self.allowed_processes.get(pid) == Some(&info.process))
+        {
+            return Admission::AllowedByProcess(pid);
+        }
+
+        match verdict {
+            Verdict::Allowed(i) => Admission::AllowedByRule(i),
+            _ => Admission::NotAllowed,
+        }
+    }
+
+    fn focus_settled(&mut self, info: WindowInfo) {
+        self.focused_window = Some(info.clone());
+        if self.follows_focus && self.pinned_window.is_none() {
+            self.show_window(info);
+        }
+    }
+
+    /// 許可されていればそのウィンドウに切り替える
+    fn show_window(&mut self, info: WindowInfo) {
+        let id = info.id;
+        let admission = self.admission(&info);
+        if !admission.is_allowed() {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("[{id}] {admission}"),
+            });
+            self.show_placeholder();
+            return;
+        }
+
+        self.make_current(info, admission);
+    }
+
+    fn make_current(&mut self, info: WindowInfo, admission: Admission) {
+        let id = info.id;
+        self.current_window = Some(id);
+        self.hide_placeholder();
+        if !self.caps.contains_key(&id) {
+            self.start_capture_for(id);
+        }
+        if let Some(cap) = self.caps.get_mut(&id) {
+            cap.info = Some(info);
+            cap.admission = Some(admission);
+        }
+    }
+
+    /// 許可やルールが変わったあと、もう出してはいけないウィンドウのキャプチャを止める
+    fn stop_disallowed_captures(&mut self) {
+        let admissions: Vec<_> = self
+            .caps
+            .iter()
+            .filter_map(|(id, cap)| Some((*id, self.admission(cap.info.as_ref()?))))
+            .collect();
+
+        let mut stopped = vec![];
+        for (id, admission) in admissions {
+            let cap = self.caps.get_mut(&id).unwrap();
+            cap.admission = Some(admission);
+
+            // ピン留めは許可されていなくても出せるが、拒否されたものは出さない
+            let keeps = if Some(id) == self.pinned_window {
+                !admission.is_denied()
+            } else {
+                admission.is_allowed()
+            };
+            if !keeps {
+                stopped.push(id);
             }
         }
 
-        // すでに閉じられたウィンドウを削除する
-        for hwnd in to_remove {
-            if let Some(cap) = self.caps.remove(&hwnd.0) {
-                let _ = cap.thread.join();
+        for id in stopped {
+            self.stop_capture(id);
+            self.drop_delayed_frames(id);
+            if self.pinned_window == Some(id) {
+                self.pinned_window = None;
+            }
+            if self.current_window == Some(id) {
+                self.current_window = None;
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                    message: format!("[{id}] is no longer shown"),
+                });
+                self.show_placeholder();
             }
         }
     }
 
-    fn handle_captures_frames(&mut self) {
-        for WindowCaptureInterop { rx_frame, .. } in self.caps.values_mut() {
-            if let Ok(frame) = rx_frame.try_recv() {
-                if Some(frame.hwnd) == self.current_hwnd {
-                    let _ = self.im_tx_cmd.send(ImageViewerCommand::Update(frame));
+    /// 遅延の中でまだ出ていないフレームも、出してはいけなくなったら出さない
+    fn drop_delayed_frames(&mut self, id: WindowId) {
+        let dropped = self.delay.drop_window(id);
+        if dropped > 0 {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("[{id}] dropped {dropped} delayed frames"),
+            });
+        }
+    }
+
+    /// 出してよいウィンドウがなくなったので、設定に従って代わりの画面を出す
+    fn show_placeholder(&mut self) {
+        self.current_window = None;
+        if self.shows_placeholder {
+            return;
+        }
+
+        self.shows_placeholder = true;
+        let placeholder = &self.config.placeholder;
+        self.slate_deadline = match placeholder.policy {
+            PlaceholderPolicy::Hold => placeholder.hold_timeout().map(|timeout| self.now + timeout),
+            _ => None,
+        };
+        self.send_placeholder(placeholder.policy);
+    }
+
+    fn hide_placeholder(&mut self) {
+        self.shows_placeholder = false;
+        self.slate_deadline = None;
+    }
+
+    fn send_placeholder(&mut self, policy: PlaceholderPolicy) {
+        let placeholder = &self.config.placeholder;
+        if let Some(frame) = placeholder.render(policy, self.last_frame.as_ref()) {
+            self.present(frame);
+        }
+    }
+
+    /// 出力の大きさにそろえ、出すものが変わったばかりなら切り替えの途中の絵にして、遅延を挟んで
+    /// ビューアーに送る。ブラックアウト中は何も送らない
+    fn present(&mut self, frame: CapturedFrame) {
+        if self.blackout {
+            return;
+        }
+
+        let frame = self.canvas.fit(frame, &self.config.canvas);
+        let frame = self
+            .transition
+            .process(frame, &self.config.transition, self.now);
+        self.output(frame);
+    }
+
+    /// ビューアーに送るものはすべてここを通す
+    fn output(&mut self, frame: CapturedFrame) {
+        if self.blackout {
+            return;
+        }
+
+        self.delay.push(frame, &self.config.delay, self.now);
+        self.send_delayed_frames();
+    }
+
+    fn send_delayed_frames(&mut self) {
+        if let Some(frame) = self.delay.poll(self.now) {
+            self.send_to_viewer(frame);
+        }
+    }
+
+    /// 送ったものはあとで書き出せるよう覚えておく
+    fn send_to_viewer(&mut self, frame: CapturedFrame) {
+        self.replay
+            .record(frame.clone(), &self.config.replay, self.now);
+        let _ = self.im_tx_cmd.send(ImageViewerCommand::Update(frame));
+    }
+
+    /// replay_secs が None なら今の設定を表示するだけ
+    fn change_replay(&mut self, replay_secs: Option<f64>) {
+        let Some(secs) = replay_secs else {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!(
+                    "replay {}, {:.1}s recorded",
+                    self.config.replay,
+                    self.replay.duration().as_secs_f64()
+                ),
+            });
+            return;
+        };
+
+        self.config.replay.secs = secs;
+        if self.config.replay.length().is_zero() {
+            self.replay.clear();
+        }
+        self.save_config(format!("replay {}", self.config.replay));
+    }
+
+    /// 書き出しには時間がかかるので、別のスレッドに任せて終わったら知らせる
+    fn save_replay(&self, path: PathBuf) {
+        let clip = self.replay.clip();
+        if clip.is_empty() {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: "nothing to save (turn recording on with `replay <secs>`)".into(),
+            });
+            return;
+        }
+
+        let sh_tx_cmd = self.sh_tx_cmd.clone();
+        thread::spawn(move || {
+            let message = match clip.save_y4m(&path) {
+                Ok(count) => format!("saved {count} frames to {}", path.display()),
+                Err(e) => format!("failed to save replay: {}: {e}", path.display()),
+            };
+            let _ = sh_tx_cmd.send(StdinShellCommand::Output { message });
+        });
+    }
+
+    /// delay_secs が None なら今の設定を表示するだけ
+    fn change_delay(&mut self, delay_secs: Option<f64>) {
+        let Some(secs) = delay_secs else {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!(
+                    "delay {}, {} frames queued, {} dropped over the limit",
+                    self.config.delay,
+                    self.delay.queued(),
+                    self.delay.dropped()
+                ),
+            });
+            return;
+        };
+
+        // 溜まっているフレームは元の予定どおりに出る
+        self.config.delay.secs = secs;
+        self.save_config(format!("delay {}", self.config.delay));
+    }
+
+    /// 切り替えの途中でも待たずに真っ黒にする。解除したら今出すべきものに戻す
+    fn change_blackout(&mut self, change: BlackoutChange) {
+        let blackout = change.apply(self.blackout);
+        if blackout == self.blackout {
+            let state = if blackout { "on" } else { "off" };
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("blackout is already {state}"),
+            });
+            return;
+        }
+
+        self.blackout = blackout;
+        if blackout {
+            // 遅延で溜まっていたフレームもひとつも出さない
+            let dumped = self.delay.dump();
+            if dumped > 0 {
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                    message: format!("dumped {dumped} delayed frames"),
+                });
+            }
+            // 解除したときにブラックアウト前の絵から切り替わらないよう、途中の切り替えも捨てる
+            self.transition = TransitionStage::default();
+            let frame = blackout_frame(self.last_frame.as_ref());
+            let frame = self.canvas.fit(frame, &self.config.canvas);
+            self.send_to_viewer(frame);
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: "blackout on: nothing is shown until `blackout off`".into(),
+            });
+            return;
+        }
+
+        // hold で時間切れになったあとならスレートを出していたはず
+        let placeholder = &self.config.placeholder;
+        let policy = match placeholder.policy {
+            _ if !self.shows_placeholder => PlaceholderPolicy::Hold,
+            PlaceholderPolicy::Hold
+                if self.slate_deadline.is_none() && placeholder.hold_timeout().is_some() =>
+            {
+                PlaceholderPolicy::Slate
+            }
+            policy => policy,
+        };
+        let frame = placeholder
+            .render(policy, self.last_frame.as_ref())
+            .or_else(|| self.last_frame.clone());
+        if let Some(frame) = frame {
+            self.present(frame);
+        }
+        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+            message: "blackout off".into(),
+        });
+    }
+
+    fn handle_deadlines(&mut self) {
+        if let Some(info) = self.focus.poll(self.now) {
+            self.focus_settled(info);
+        }
+
+        if let Some(frame) = self.transition.tick(self.now) {
+            self.output(frame);
+        }
+        self.send_delayed_frames();
+
+        if self
+            .slate_deadline
+            .is_some_and(|deadline| deadline <= self.now)
+        {
+            self.slate_deadline = None;
+            self.send_placeholder(PlaceholderPolicy::Slate);
+        }
+    }
+
+    /// 当てはまる許可ルールがあればそこに保存する。なければ終了するまでのあいだだけ覚えておく
+    fn set_crop(&mut self, info: WindowInfo, crop: Option<Crop>) {
+        let id = info.id;
+        let shown = crop.map_or("off".to_owned(), |crop| crop.to_string());
+        if let Verdict::Allowed(i) = self.config.judge(&info) {
+            self.crops.remove(&id);
+            self.config.allow[i - 1].crop = crop;
+            self.save_config(format!("[{id}] crop {shown} saved to rule #{i}"));
+            return;
+        }
+
+        match crop {
+            Some(crop) => self.crops.insert(id, crop),
+            None => self.crops.remove(&id),
+        };
+        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+            message: format!("[{id}] crop {shown} (not saved: no allow rule matches)"),
+        });
+    }
+
+    /// 同じ名前の範囲があれば置き換える。保存先は set_crop と同じ
+    fn add_mask(&mut self, info: WindowInfo, mask: Mask) {
+        let id = info.id;
+        let shown = mask.to_string();
+        let (masks, saved_to) = match self.config.judge(&info) {
+            Verdict::Allowed(i) => (&mut self.config.allow[i - 1].masks, Some(i)),
+            _ => (self.masks.entry(id).or_default(), None),
+        };
+        masks.retain(|m| m.name != mask.name);
+        masks.push(mask);
+
+        match saved_to {
+            Some(i) => self.save_config(format!("[{id}] mask {shown} saved to rule #{i}")),
+            None => {
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                    message: format!("[{id}] mask {shown} (not saved: no allow rule matches)"),
+                });
+            }
+        }
+    }
+
+    fn remove_mask(&mut self, info: WindowInfo, name: &str) {
+        let id = info.id;
+        if let Some(masks) = self.masks.get_mut(&id) {
+            if let Some(i) = masks.iter().position(|mask| mask.name == name) {
+                masks.remove(i);
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                    message: format!("[{id}] mask {name} removed"),
+                });
+                return;
+            }
+        }
+
+        let rule = self
+            .config
+            .allow
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, rule)| rule.matches(&info))
+            .find(|(_, rule)| rule.masks.iter().any(|mask| mask.name == name));
+        match rule {
+            Some((i, rule)) => {
+                rule.masks.retain(|mask| mask.name != name);
+                self.save_config(format!("[{id}] mask {name} removed from rule #{}", i + 1));
+            }
+            None => {
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                    message: format!("[{id}] no such mask {name}"),
+                });
+            }
+        }
+    }
+
+    /// info がなければ、キャプチャしているすべてのウィンドウについて表示する
+    fn list_masks(&self, info: Option<&WindowInfo>) {
+        let windows = match info {
+            Some(info) => vec![info],
+            None => self
+                .caps
+                .values()
+                .filter_map(|cap| cap.info.as_ref())
+                .collect(),
+        };
+
+        let mut buf = String::new();
+        writeln!(buf, "Masks:").unwrap();
+        for info in windows {
+            let session = self.masks.get(&info.id).into_iter().flatten();
+            for mask in session {
+                writeln!(buf, "| [{}] {mask} (not saved)", info.id).unwrap();
+            }
+            for (i, rule) in self.config.allow.iter().enumerate() {
+                if rule.matches(info) {
+                    for mask in &rule.masks {
+                        writeln!(buf, "| [{}] {mask} (rule #{})", info.id, i + 1).unwrap();
+                    }
                 }
             }
         }
+
+        let _ = self
+            .sh_tx_cmd
+            .send(StdinShellCommand::Output { message: buf });
+    }
+
+    /// 隠してから切り抜く。隠す範囲は切り抜く前のウィンドウ全体が基準。タイトルで隠すことに
+    /// なっていれば、最後に全体を隠す
+    ///
+    /// ルールの隠す範囲と切り抜きは、フレームごとに今のタイトルで選び直す。タイトルが変わって
+    /// ルールに当てはまらなくなれば、そのルールの範囲は外れる
+    fn process_frame(&self, frame: CapturedFrame) -> CapturedFrame {
+        let info = self.caps.get(&frame.id).and_then(|cap| cap.info.as_ref());
+        let session = self.masks.get(&frame.id).into_iter().flatten();
+        let masks = session.chain(info.into_iter().flat_map(|info| self.config.masks(info)));
+        let frame = apply_masks(frame, masks);
+
+        let crop = self
+            .crops
+            .get(&frame.id)
+            .copied()
+            .or_else(|| self.config.crop(info?));
+
+        let frame = match crop {
+            Some(crop) => crop.apply(&frame),
+            None => frame,
+        };
+
+        if self.is_redacted(frame.id) {
+            self.config.redact.render(&frame)
+        } else {
+            frame
+        }
     }
 
-    fn start_capture_for(&mut self, hwnd: HWND) {
-        let (tx_frame, rx_frame) = bounded(5);
-        let (capture, tx_cmd, rx_msg) = WindowCapture::new(hwnd, tx_frame);
-        let thread = thread::spawn(move || capture.run());
-        self.caps.insert(
-            hwnd.0,
-            WindowCaptureInterop {
-                _tx_cmd: tx_cmd,
-                rx_msg,
-                rx_frame,
-                thread,
-            },
-        );
+    fn is_redacted(&self, id: WindowId) -> bool {
+        self.caps
+            .get(&id)
+            .and_then(|cap| cap.info.as_ref())
+            .is_some_and(|info| self.config.redact.matching(&info.title).is_some())
     }
 
-    fn cleanup_threads(&mut self) {
-        let mut to_remove = vec![];
-        for (hwnd_id, WindowCaptureInterop { thread, .. }) in &self.caps {
-            if thread.is_finished() {
-                to_remove.push(*hwnd_id);
+    /// キャプチャ中にタイトルが変わった。隠すことになったら次のフレームを待たずに隠す
+    fn change_title(&mut self, id: WindowId, title: String) {
+        let was_redacted = self.is_redacted(id);
+        let Some(info) = self.caps.get_mut(&id).and_then(|cap| cap.info.as_mut()) else {
+            return;
+        };
+        // 最初の報告はフォーカスされたときに調べたタイトルと同じことが多い
+        if info.title == title {
+            return;
+        }
+        info.title = title;
+
+        match self.config.redact.matching(&info.title) {
+            Some(pattern) if !was_redacted => {
+                let message = format!("[{id}] redacted: title matches {pattern}");
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
+                self.drop_delayed_frames(id);
+                self.redact_last_frame(id);
             }
+            None if was_redacted => {
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                    message: format!("[{id}] no longer redacted"),
+                });
+            }
+            _ => {}
+        }
+
+        // タイトルを条件にした許可・拒否ルールを当てはめ直す
+        self.stop_disallowed_captures();
+    }
+
+    fn redact_last_frame(&mut self, id: WindowId) {
+        if self.current_window != Some(id) {
+            return;
+        }
+
+        if let Some(frame) = self.last_frame.take() {
+            let frame = self.config.redact.render(&frame);
+            self.last_frame = Some(frame.clone());
+            self.present(frame);
         }
+    }
 
-        for hwnd_id in to_remove {
+    /// change が None なら今の設定を表示するだけ
+    fn change_redact(&mut self, change: Option<RedactChange>) {
+        let Some(change) = change else {
             let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
-                message: format!("[{hwnd_id}] thread is finished"),
+                message: format!("redact {}", self.config.redact),
             });
-            if let Some(cap) = self.caps.remove(&hwnd_id) {
-                let _ = cap.thread.join();
+            return;
+        };
+
+        let was_redacted = self.current_window.is_some_and(|id| self.is_redacted(id));
+        if let Err(message) = self.config.redact.apply(change) {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
+            return;
+        }
+        if let Some(id) = self.current_window {
+            if !was_redacted && self.is_redacted(id) {
+                self.redact_last_frame(id);
             }
         }
+
+        self.save_config(format!("redact {}", self.config.redact));
     }
 
-    fn quit(&mut self) {
-        self.is_running = false;
-        let _ = self.im_tx_cmd.send(ImageViewerCommand::Quit);
-        let _ = self.fw_tx_cmd.send(ForegroundWatcherCommand::Quit);
-        let _ = self.sh_tx_cmd.send(StdinShellCommand::Quit);
+    /// changes が空なら今の設定を表示するだけ
+    fn change_canvas(&mut self, changes: Vec<CanvasChange>) {
+        if changes.is_empty() {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("canvas {}", self.config.canvas),
+            });
+            return;
+        }
+
+        for change in changes {
+            self.config.canvas.apply(change);
+        }
+        self.save_config(format!("canvas {}", self.config.canvas));
+    }
+
+    /// change が None なら今の設定を表示するだけ。時間を省くと今の時間のまま
+    fn change_transition(&mut self, change: Option<(TransitionKind, Option<u64>)>) {
+        let Some((kind, duration_ms)) = change else {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("transition {}", self.config.transition),
+            });
+            return;
+        };
+
+        self.config.transition.kind = kind;
+        if let Some(duration_ms) = duration_ms {
+            self.config.transition.duration_ms = duration_ms;
+        }
+        self.save_config(format!("transition {}", self.config.transition));
+    }
+
+    /// dwell_ms が None なら今の設定を表示するだけ
+    fn change_dwell(&mut self, dwell_ms: Option<u64>) {
+        let Some(dwell_ms) = dwell_ms else {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("dwell {}ms", self.config.focus.dwell_ms),
+            });
+            return;
+        };
+
+        self.config.focus.dwell_ms = dwell_ms;
+        self.save_config(format!("dwell {dwell_ms}ms"));
+    }
+
+    /// change が None なら今の設定を表示するだけ
+    fn change_placeholder(&mut self, change: Option<PlaceholderChange>) {
+        let Some(change) = change else {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("placeholder: {}", self.config.placeholder),
+            });
+            return;
+        };
+
+        self.config.placeholder.apply(change);
+        // 出している最中なら新しい設定で出し直す
+        if self.shows_placeholder {
+            self.shows_placeholder = false;
+            self.show_placeholder();
+        }
+
+        self.save_config(format!("placeholder: {}", self.config.placeholder));
+    }
+
+    fn revoke(&mut self, windows: Vec<WindowInfo>) {
+        for info in &windows {
+            self.allowed_windows.remove(&info.id);
+            self.allowed_owners.remove(&info.id);
+            if let Some(pid) = info.pid {
+                self.allowed_processes.remove(&pid);
+            }
+        }
+        self.stop_disallowed_captures();
+
+        let ids: Vec<_> = windows.iter().map(|info| info.id.to_string()).collect();
+        self.report_state(format!("revoked {}", ids.join(", ")));
+    }
+
+    fn deny(&mut self, ids: Vec<WindowId>) {
+        for id in &ids {
+            self.allowed_windows.remove(id);
+            self.allowed_owners.remove(id);
+            self.denied_windows.insert(*id);
+        }
+        self.stop_disallowed_captures();
+
+        let ids: Vec<_> = ids.iter().map(WindowId::to_string).collect();
+        self.report_state(format!("denied {}", ids.join(", ")));
+    }
+
+    /// 個別の許可をすべて取り消す。拒否と設定ファイルのルールはそのまま
+    fn clear(&mut self) {
+        self.allowed_windows.clear();
+        self.allowed_processes.clear();
+        self.allowed_owners.clear();
+        self.stop_disallowed_captures();
+
+        self.report_state("cleared allowed windows, processes and owners".into());
+    }
+
+    fn pin(&mut self, info: WindowInfo) {
+        let id = info.id;
+        let admission = self.admission(&info);
+        if admission.is_denied() {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("[{id}] cannot be pinned: {admission}"),
+            });
+            return;
+        }
+
+        self.pinned_window = Some(id);
+        self.make_current(info, admission);
+
+        self.report_state(format!("[{id}] pinned"));
+    }
+
+    fn unpin(&mut self) {
+        let Some(id) = self.pinned_window.take() else {
+            self.report_state("nothing is pinned".into());
+            return;
+        };
+
+        self.follow_focused_window();
+        self.report_state(format!("[{id}] unpinned"));
+    }
+
+    fn follow(&mut self, follows: bool) {
+        self.follows_focus = follows;
+        if follows && self.pinned_window.is_none() {
+            self.follow_focused_window();
+        }
+
+        self.report_state(format!("follow {}", if follows { "on" } else { "off" }));
+    }
+
+    /// 追従を再開したときは、今フォーカスされているウィンドウに合わせる
+    fn follow_focused_window(&mut self) {
+        if let Some(info) = self.focused_window.clone() {
+            self.show_window(info);
+        }
+    }
+
+    fn report_state(&self, message: String) {
+        let name = |id: Option<WindowId>| id.map_or("none".to_owned(), |id| id.to_string());
+        let message = format!(
+            "{message} (current: {}, pinned: {}, follow: {})",
+            name(self.current_window),
+            name(self.pinned_window),
+            if self.follows_focus { "on" } else { "off" }
+        );
+        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
+    }
+
+    fn allow_processes(&mut self, windows: Vec<WindowInfo>) {
+        let mut buf = String::new();
+        for info in windows {
+            let Some(pid) = info.pid else {
+                writeln!(buf, "[{}] process is unknown", info.id).unwrap();
+                continue;
+            };
+
+            writeln!(
+                buf,
+                "process {} ({}) allowed",
+                pid,
+                info.process.as_deref().unwrap_or("?")
+            )
+            .unwrap();
+            self.allowed_processes.insert(pid, info.process);
+        }
+
+        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+            message: buf.trim_end().to_owned(),
+        });
+    }
+
+    fn add_rule(&mut self, kind: RuleKind, rule: WindowRule) {
+        let rules = self.config.rules_mut(kind);
+        rules.push(rule);
+        let message = format!(
+            "{}rule #{} added: {}",
+            kind.prefix(),
+            rules.len(),
+            rules.last().unwrap()
+        );
+        self.save_config(message);
+        self.stop_disallowed_captures();
+    }
+
+    /// `index` は `list` に表示される 1 始まりの番号
+    fn remove_rule(&mut self, kind: RuleKind, index: usize) {
+        let rules = self.config.rules_mut(kind);
+        if index == 0 || index > rules.len() {
+            let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                message: format!("no such {}rule #{index}", kind.prefix()),
+            });
+            return;
+        }
+
+        let rule = rules.remove(index - 1);
+        self.save_config(format!("{}rule #{index} removed: {rule}", kind.prefix()));
+        self.stop_disallowed_captures();
+    }
+
+    /// 式を指定したときはその式だけを、そうでなければ今のルールをウィンドウに当てはめてみる
+    fn test_rules(&self, expr: Option<RuleExpr>, windows: &[ScanEntry]) {
+        let mut buf = String::new();
+        match &expr {
+            Some(expr) => writeln!(buf, "Testing {expr}:").unwrap(),
+            None => writeln!(buf, "Testing rules:").unwrap(),
+        }
+
+        for entry in windows {
+            let result = match &expr {
+                Some(expr) if expr.eval(&entry.info) => "matches".to_owned(),
+                Some(_) => "does not match".to_owned(),
+                None => self.admission(&entry.info).to_string(),
+            };
+            writeln!(
+                buf,
+                "| {}) [{:>12}] {} -> {}",
+                entry.alias.unwrap_or(' '),
+                entry.info.id,
+                entry.info.title,
+                result
+            )
+            .unwrap();
+        }
+
+        let _ = self
+            .sh_tx_cmd
+            .send(StdinShellCommand::Output { message: buf });
+    }
+
+    fn save_config(&mut self, message: String) {
+        let message = match self.config.save() {
+            Ok(()) => message,
+            Err(e) => format!("{message}\nfailed to save config: {e}"),
+        };
+        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
+    }
+
+    fn handle_captures_message(&mut self) {
+        let mut to_remove = vec![];
+        let mut finished = vec![];
+        let mut titles = vec![];
+        for (id, WindowCaptureInterop { rx_msg, .. }) in &self.caps {
+            loop {
+                match rx_msg.try_recv() {
+                    Ok(WindowCaptureMessage::Closed { id }) => to_remove.push(id),
+                    Ok(WindowCaptureMessage::TitleChanged { id, title }) => {
+                        titles.push((id, title))
+                    }
+                    Ok(WindowCaptureMessage::Output { message }) => {
+                        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    // キャプチャのスレッドが終わるとチャンネルが切断される
+                    Err(TryRecvError::Disconnected) => {
+                        finished.push(*id);
+                        break;
+                    }
+                }
+            }
+        }
+
+        for (id, title) in titles {
+            self.change_title(id, title);
+        }
+
+        // すでに閉じられたウィンドウを削除する
+        for id in to_remove {
+            if let Some(cap) = self.caps.remove(&id) {
+                let _ = cap.thread.join();
+            }
+            self.forget_current_window(id);
+
+            self.focus.forget(id);
+
+            if self
+                .focused_window
+                .as_ref()
+                .is_some_and(|info| info.id == id)
+            {
+                self.focused_window = None;
+            }
+            if self.pinned_window == Some(id) {
+                self.pinned_window = None;
+                if self.follows_focus {
+                    self.follow_focused_window();
+                }
+                self.report_state(format!("[{id}] pinned window is closed"));
+            }
+        }
+
+        for id in finished {
+            if let Some(cap) = self.caps.remove(&id) {
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                    message: format!("[{id}] thread is finished"),
+                });
+                let _ = cap.thread.join();
+            }
+            self.forget_current_window(id);
+        }
+
+        // 止めたキャプチャはメッセージを伝えるだけにして、スレッドが終わったら片付ける
+        let mut stopping = vec![];
+        for (id, cap) in self.stopping.drain(..) {
+            let finished = loop {
+                match cap.rx_msg.try_recv() {
+                    Ok(WindowCaptureMessage::Output { message }) => {
+                        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output { message });
+                    }
+                    Ok(_) => {}
+                    Err(TryRecvError::Empty) => break false,
+                    Err(TryRecvError::Disconnected) => break true,
+                }
+            };
+            if finished {
+                let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+                    message: format!("[{id}] thread is finished"),
+                });
+                let _ = cap.thread.join();
+            } else {
+                stopping.push((id, cap));
+            }
+        }
+        self.stopping = stopping;
+    }
+
+    fn handle_captures_frames(&mut self) {
+        // 現在のウィンドウ以外のフレームは読み捨てる
+        let mut latest = None;
+        for WindowCaptureInterop { frames, .. } in self.caps.values() {
+            if let Some(frame) = frames.take() {
+                if Some(frame.id) == self.current_window {
+                    latest = Some(frame);
+                }
+            }
+        }
+
+        if let Some(frame) = latest {
+            let frame = self.process_frame(frame);
+            self.last_frame = Some(frame.clone());
+            self.present(frame);
+        }
+    }
+
+    fn show_test_pattern(&mut self, config: TestPatternConfig) {
+        // 古いテストパターンは不要なので止める
+        let old: Vec<_> = self
+            .caps
+            .keys()
+            .copied()
+            .filter(|id| id.backend() == Backend::TestPattern)
+            .collect();
+        for id in old {
+            self.stop_capture(id);
+        }
+
+        let id = WindowId::new(Backend::TestPattern, self.next_test_pattern);
+        self.next_test_pattern += 1;
+
+        let backend = (self.test_pattern_backend)(config);
+        self.start_capture_with(id, backend);
+        self.current_window = Some(id);
+        self.hide_placeholder();
+
+        let TestPatternConfig { width, height, fps } = config;
+        let _ = self.sh_tx_cmd.send(StdinShellCommand::Output {
+            message: format!("[{id}] showing test pattern {width}x{height}@{fps}"),
+        });
+    }
+
+    fn start_capture_for(&mut self, id: WindowId) {
+        let backend = (self.capture_backend)(id);
+        self.start_capture_with(id, backend);
+    }
+
+    fn start_capture_with(&mut self, id: WindowId, backend: Box<dyn CaptureBackend>) {
+        let (poster, frames) = frame_mailbox();
+        let (capture, tx_cmd, rx_msg) = WindowCapture::new(id, backend, poster);
+        let thread = thread::spawn(move || capture.run());
+        self.caps.insert(
+            id,
+            WindowCaptureInterop {
+                tx_cmd,
+                rx_msg,
+                frames,
+                thread,
+                info: None,
+                admission: None,
+            },
+        );
+    }
+
+    /// 出していたウィンドウのキャプチャが終わったら、最後のフレームを出したままにせず代わりの画面にする
+    fn forget_current_window(&mut self, id: WindowId) {
+        if self.current_window == Some(id) {
+            self.current_window = None;
+            self.show_placeholder();
+        }
+    }
+
+    /// スレッドが終わるのは待たずに、すぐ caps から外す
+    fn stop_capture(&mut self, id: WindowId) {
+        if let Some(cap) = self.caps.remove(&id) {
+            let _ = cap.tx_cmd.send(WindowCaptureCommand::Stop);
+            self.stopping.push((id, cap));
+        }
+    }
+
+    fn quit(&mut self) {
+        self.is_running = false;
+        let _ = self.im_tx_cmd.send(ImageViewerCommand::Quit);
+        let _ = self.fw_tx_cmd.send(ForegroundWatcherCommand::Quit);
+        let _ = self.sh_tx_cmd.send(StdinShellCommand::Quit);
+        if let Some(ctl_tx_cmd) = &self.ctl_tx_cmd {
+            let _ = ctl_tx_cmd.send(ControlCommand::Quit);
+        }
+        for cap in self.caps.values() {
+            let _ = cap.tx_cmd.send(WindowCaptureCommand::Stop);
+        }
+    }
+}
+
+impl Admission {
+    fn is_denied(self) -> bool {
+        matches!(self, Admission::Denied | Admission::DeniedByRule(_))
+    }
+
+    fn is_allowed(self) -> bool {
+        matches!(
+            self,
+            Admission::Allowed
+                | Admission::AllowedByProcess(_)
+                | Admission::AllowedByOwner(_)
+                | Admission::AllowedByRule(_)
+        )
+    }
+}
+
+impl fmt::Display for Admission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Admission::Allowed => f.write_str("allowed"),
+            Admission::AllowedByProcess(pid) => write!(f, "allowed by process {pid}"),
+            Admission::AllowedByOwner(owner) => write!(f, "allowed as owned by {owner}"),
+            Admission::AllowedByRule(i) => write!(f, "allowed by rule #{i}"),
+            Admission::Denied => f.write_str("denied"),
+            Admission::DeniedByRule(i) => write!(f, "denied by deny rule #{i}"),
+            Admission::NotAllowed => f.write_str("not allowed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crossbeam_channel::unbounded;
+
+    use super::*;
+    use crate::{
+        config::TitlePattern,
+        mock::{mock_window, mock_window_info, MockScenario, STEP},
+        placeholder::{placeholder_id, PlaceholderConfig},
+        transition::TransitionConfig,
+    };
+
+    struct Outcome {
+        // 連続する同じウィンドウのフレームはまとめてある
+        shown: Vec<WindowId>,
+        // ビューアーに送られたすべてのフレーム
+        frames: Vec<CapturedFrame>,
+        shell_output: Vec<String>,
+        // 終了直前の list の出力
+        list: String,
+    }
+
+    fn allow(windows: &[u64]) -> StdinShellMessage {
+        StdinShellMessage::Allow(windows.iter().copied().map(mock_window).collect())
+    }
+
+    fn run_scenario(script: &str, messages: Vec<StdinShellMessage>, duration: Duration) -> Outcome {
+        let messages = messages
+            .into_iter()
+            .map(|msg| (Duration::ZERO, msg))
+            .collect();
+        run_timed_scenario(script, messages, duration)
+    }
+
+    /// シェルからのメッセージを、スクリプトと同じ起点からの指定した時刻に送る。同じ時刻なら
+    /// シェルからのメッセージを先に処理する
+    fn run_timed_scenario(
+        script: &str,
+        messages: Vec<(Duration, StdinShellMessage)>,
+        duration: Duration,
+    ) -> Outcome {
+        run_configured_scenario(Config::default(), script, messages, duration)
+    }
+
+    fn run_configured_scenario(
+        config: Config,
+        script: &str,
+        messages: Vec<(Duration, StdinShellMessage)>,
+        duration: Duration,
+    ) -> Outcome {
+        // 時計はハーネスが STEP ずつ進める。実際に時間がたつのを待つことはない
+        let epoch = Instant::now();
+        let mut scenario = MockScenario::new(script, epoch).unwrap();
+
+        let (im_tx_cmd, im_rx_cmd) = unbounded();
+        let (_im_tx_msg, im_rx_msg) = unbounded();
+        let (fw_tx_cmd, _fw_rx_cmd) = unbounded();
+        let (fw_tx_msg, fw_rx_msg) = unbounded();
+        let (sh_tx_cmd, sh_rx_cmd) = unbounded();
+        let (sh_tx_msg, sh_rx_msg) = unbounded();
+
+        let mut driver = Driver::new(
+            im_tx_cmd,
+            im_rx_msg,
+            fw_tx_cmd,
+            fw_rx_msg,
+            sh_tx_cmd,
+            sh_rx_msg,
+            scenario.capture_backend(),
+        )
+        .with_config(config)
+        .with_test_pattern_backend(scenario.test_pattern_backend());
+
+        let mut messages = messages;
+        messages.sort_by_key(|(at, _)| *at);
+        let mut messages = messages.into_iter().peekable();
+        let mut at = Duration::ZERO;
+        loop {
+            let now = epoch + at;
+            while let Some((_, msg)) = messages.next_if(|(msg_at, _)| *msg_at <= at) {
+                let _ = sh_tx_msg.send(msg);
+            }
+            driver.turn(now);
+
+            for info in scenario.take_focus_changes(at) {
+                let _ = fw_tx_msg.send(ForegroundWatcherMessage::WindowChanged { info });
+            }
+            scenario.tick(now);
+            driver.turn(now);
+
+            if at >= duration {
+                break;
+            }
+            at += STEP;
+        }
+
+        let _ = sh_tx_msg.send(StdinShellMessage::ListRequested);
+        let _ = sh_tx_msg.send(StdinShellMessage::QuitRequested);
+        driver.turn(epoch + duration);
+        scenario.finish();
+
+        let mut shown: Vec<WindowId> = vec![];
+        let mut frames = vec![];
+        for cmd in im_rx_cmd.try_iter() {
+            if let ImageViewerCommand::Update(frame) = cmd {
+                if shown.last() != Some(&frame.id) {
+                    shown.push(frame.id);
+                }
+                frames.push(frame);
+            }
+        }
+
+        let mut shell_output: Vec<_> = sh_rx_cmd
+            .try_iter()
+            .filter_map(|cmd| match cmd {
+                StdinShellCommand::Output { message } => Some(message),
+                StdinShellCommand::Quit => None,
+            })
+            .collect();
+        let list = shell_output.pop().unwrap();
+
+        Outcome {
+            shown,
+            frames,
+            shell_output,
+            list,
+        }
+    }
+
+    #[test]
+    fn follows_focus_between_allowed_windows() {
+        let outcome = run_scenario(
+            "t=0 focus 1; t=200ms focus 2; t=400ms focus 1",
+            vec![allow(&[1, 2])],
+            Duration::from_millis(600),
+        );
+
+        assert_eq!(
+            outcome.shown,
+            [mock_window(1), mock_window(2), mock_window(1)]
+        );
+    }
+
+    #[test]
+    fn keeps_last_window_when_focus_moves_to_disallowed_one() {
+        let outcome = run_scenario(
+            "t=0 focus 1; t=200ms focus 3",
+            vec![allow(&[1])],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1)]);
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "[mock:3] not allowed"));
+    }
+
+    #[test]
+    fn nothing_is_shown_before_any_window_is_allowed() {
+        let outcome = run_scenario(
+            "t=0 focus 1; t=100ms focus 2",
+            vec![],
+            Duration::from_millis(300),
+        );
+
+        assert!(outcome.shown.is_empty());
+    }
+
+    #[test]
+    fn closed_window_stops_producing_frames() {
+        let outcome = run_scenario(
+            "t=0 focus 1; t=200ms focus 2; t=300ms close 2; t=400ms focus 1",
+            vec![allow(&[1, 2])],
+            Duration::from_millis(600),
+        );
+
+        assert_eq!(
+            outcome.shown,
+            [mock_window(1), mock_window(2), mock_window(1)]
+        );
+    }
+
+    #[test]
+    fn shows_test_pattern_until_allowed_window_is_focused() {
+        let pattern = TestPatternConfig {
+            width: 64,
+            height: 36,
+            fps: 30,
+        };
+        let outcome = run_scenario(
+            "t=200ms focus 1",
+            vec![allow(&[1]), StdinShellMessage::ShowTestPattern(pattern)],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(
+            outcome.shown,
+            [WindowId::new(Backend::TestPattern, 1), mock_window(1)]
+        );
+    }
+
+    #[test]
+    fn replaced_test_pattern_thread_is_reaped() {
+        let pattern = TestPatternConfig {
+            width: 64,
+            height: 36,
+            fps: 30,
+        };
+        let outcome = run_scenario(
+            "",
+            vec![
+                StdinShellMessage::ShowTestPattern(pattern),
+                StdinShellMessage::ShowTestPattern(pattern),
+            ],
+            Duration::from_millis(300),
+        );
+
+        assert_eq!(outcome.shown, [WindowId::new(Backend::TestPattern, 2)]);
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "[testpattern:1] thread is finished"));
+    }
+
+    #[test]
+    fn closing_shown_window_shows_placeholder() {
+        let config = placeholder_config(PlaceholderConfig {
+            hold_timeout_secs: Some(0.1),
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=100ms close 1",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(400),
+        );
+
+        // 最後のフレームを出したままにするのは hold の間だけ
+        assert_eq!(outcome.shown, [mock_window(1), placeholder_id()]);
+    }
+
+    #[test]
+    fn huge_hold_timeout_keeps_last_frame() {
+        let config = placeholder_config(PlaceholderConfig {
+            hold_timeout_secs: Some(1e19),
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=100ms close 1",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1)]);
+    }
+
+    #[test]
+    fn refocusing_closed_window_does_not_show_it() {
+        let outcome = run_scenario(
+            "t=0 focus 1; t=100ms close 1; t=200ms focus 2; t=300ms focus 1",
+            vec![allow(&[1, 2])],
+            Duration::from_millis(500),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), mock_window(2)]);
+    }
+
+    #[test]
+    fn admits_windows_matching_allow_rules() {
+        let rule = WindowRule {
+            process: Some("mock2".into()),
+            ..Default::default()
+        };
+        let outcome = run_scenario(
+            "t=0 focus 1; t=100ms focus 2",
+            vec![StdinShellMessage::AddRule(RuleKind::Allow, rule)],
+            Duration::from_millis(300),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(2)]);
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "rule #1 added: process=\"mock2\""));
+    }
+
+    #[test]
+    fn removed_rule_no_longer_admits_windows() {
+        let rule = WindowRule {
+            class: Some("MockWindow".into()),
+            ..Default::default()
+        };
+        let outcome = run_scenario(
+            "t=100ms focus 1",
+            vec![
+                StdinShellMessage::AddRule(RuleKind::Allow, rule),
+                StdinShellMessage::RemoveRule(RuleKind::Allow, 1),
+                StdinShellMessage::RemoveRule(RuleKind::Allow, 1),
+            ],
+            Duration::from_millis(300),
+        );
+
+        assert!(outcome.shown.is_empty());
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "no such rule #1"));
+    }
+
+    #[test]
+    fn deny_rules_override_allowed_windows() {
+        let rule = WindowRule {
+            when: Some("title ~ /window 2$/".parse().unwrap()),
+            ..Default::default()
+        };
+        let outcome = run_scenario(
+            "t=0 focus 1; t=100ms focus 2",
+            vec![
+                allow(&[1, 2]),
+                StdinShellMessage::AddRule(RuleKind::Deny, rule),
+            ],
+            Duration::from_millis(300),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1)]);
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "[mock:2] denied by deny rule #1"));
+    }
+
+    #[test]
+    fn stops_the_shown_window_when_a_deny_rule_matches_it() {
+        let rule = WindowRule {
+            when: Some("title ~ /window 1$/".parse().unwrap()),
+            ..Default::default()
+        };
+        let outcome = run_timed_scenario(
+            "t=0 focus 1; t=200ms focus 2; t=300ms focus 1",
+            vec![
+                (Duration::ZERO, allow(&[1, 2])),
+                (
+                    Duration::from_millis(100),
+                    StdinShellMessage::AddRule(RuleKind::Deny, rule),
+                ),
+            ],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), mock_window(2)]);
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "[mock:1] is no longer shown"));
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "[mock:1] denied by deny rule #1"));
+    }
+
+    #[test]
+    fn tests_rules_against_scanned_windows() {
+        let rule = WindowRule {
+            when: Some("process in [\"mock1\", \"mock2\"]".parse().unwrap()),
+            ..Default::default()
+        };
+        let windows: Vec<_> = ('A'..='C')
+            .zip(1..=3)
+            .map(|(alias, n)| ScanEntry {
+                alias: Some(alias),
+                info: mock_window_info(mock_window(n)),
+            })
+            .collect();
+        let outcome = run_scenario(
+            "",
+            vec![
+                StdinShellMessage::AddRule(RuleKind::Allow, rule),
+                StdinShellMessage::TestRules {
+                    expr: None,
+                    windows: windows.clone(),
+                },
+                StdinShellMessage::TestRules {
+                    expr: Some("title ~ /3/".parse().unwrap()),
+                    windows,
+                },
+            ],
+            Duration::from_millis(100),
+        );
+
+        assert_eq!(
+            outcome.shell_output[1],
+            "Testing rules:\n\
+             | A) [      mock:1] Mock window 1 -> allowed by rule #1\n\
+             | B) [      mock:2] Mock window 2 -> allowed by rule #1\n\
+             | C) [      mock:3] Mock window 3 -> not allowed\n"
+        );
+        assert_eq!(
+            outcome.shell_output[2],
+            "Testing title ~ /3/:\n\
+             | A) [      mock:1] Mock window 1 -> does not match\n\
+             | B) [      mock:2] Mock window 2 -> does not match\n\
+             | C) [      mock:3] Mock window 3 -> matches\n"
+        );
+    }
+
+    #[test]
+    fn allows_every_window_of_allowed_process() {
+        let outcome = run_scenario(
+            "t=0 focus 1; t=100ms focus 11; t=200ms focus 2; t=300ms focus 12",
+            vec![StdinShellMessage::AllowProcess(vec![mock_window_info(
+                mock_window(1),
+            )])],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(
+            outcome.shown,
+            [mock_window(1), mock_window(11), mock_window(12)]
+        );
+        assert_eq!(outcome.shell_output[0], "process 1 (mock1) allowed");
+        assert!(outcome.list.lines().any(|line| {
+            line.starts_with("| mock:11 (") && line.ends_with(", allowed by process 1)")
+        }));
+    }
+
+    #[test]
+    fn allowed_process_is_checked_by_name() {
+        let mut reused = mock_window_info(mock_window(1));
+        reused.process = Some("other".into());
+        let outcome = run_scenario(
+            "t=0 focus 1",
+            vec![StdinShellMessage::AllowProcess(vec![reused])],
+            Duration::from_millis(200),
+        );
+
+        assert!(outcome.shown.is_empty());
+    }
+
+    #[test]
+    fn allows_windows_owned_by_allowed_owner() {
+        let outcome = run_scenario(
+            "t=0 focus 12; t=100ms focus 2; t=200ms focus 1",
+            vec![StdinShellMessage::AllowOwner(vec![mock_window(1)])],
+            Duration::from_millis(300),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(12), mock_window(1)]);
+        assert!(outcome.list.lines().any(|line| {
+            line.starts_with("| mock:12 (") && line.ends_with(", allowed as owned by mock:1)")
+        }));
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "[mock:2] not allowed"));
+    }
+
+    #[test]
+    fn pinned_window_ignores_focus_until_unpinned() {
+        let outcome = run_timed_scenario(
+            "t=0 focus 1; t=100ms focus 2",
+            vec![
+                (Duration::ZERO, allow(&[1, 2])),
+                (
+                    Duration::from_millis(50),
+                    StdinShellMessage::Pin(mock_window_info(mock_window(3))),
+                ),
+                (Duration::from_millis(200), StdinShellMessage::Unpin),
+            ],
+            Duration::from_millis(300),
+        );
+
+        assert_eq!(
+            outcome.shown,
+            [mock_window(1), mock_window(3), mock_window(2)]
+        );
+        assert_eq!(
+            outcome.shell_output,
+            [
+                "[mock:3] pinned (current: mock:3, pinned: mock:3, follow: on)",
+                "[mock:3] unpinned (current: mock:2, pinned: none, follow: on)",
+            ]
+        );
+    }
+
+    #[test]
+    fn denied_window_cannot_be_pinned() {
+        let outcome = run_scenario(
+            "",
+            vec![
+                StdinShellMessage::Deny(vec![mock_window(1)]),
+                StdinShellMessage::Pin(mock_window_info(mock_window(1))),
+            ],
+            Duration::from_millis(100),
+        );
+
+        assert!(outcome.shown.is_empty());
+        assert_eq!(outcome.shell_output[1], "[mock:1] cannot be pinned: denied");
+    }
+
+    #[test]
+    fn follow_off_keeps_current_window() {
+        let outcome = run_timed_scenario(
+            "t=0 focus 1; t=150ms focus 2",
+            vec![
+                (Duration::ZERO, allow(&[1, 2])),
+                (Duration::from_millis(100), StdinShellMessage::Follow(false)),
+                (Duration::from_millis(250), StdinShellMessage::Follow(true)),
+            ],
+            Duration::from_millis(350),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), mock_window(2)]);
+        assert_eq!(
+            outcome.shell_output,
+            [
+                "follow off (current: mock:1, pinned: none, follow: off)",
+                "follow on (current: mock:2, pinned: none, follow: on)",
+            ]
+        );
+    }
+
+    #[test]
+    fn revoked_window_is_no_longer_shown() {
+        let outcome = run_timed_scenario(
+            "t=0 focus 1; t=200ms focus 2; t=300ms focus 1",
+            vec![
+                (Duration::ZERO, allow(&[1, 2])),
+                (
+                    Duration::from_millis(100),
+                    StdinShellMessage::Revoke(vec![mock_window_info(mock_window(1))]),
+                ),
+            ],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), mock_window(2)]);
+        assert!(outcome.shell_output.starts_with(&[
+            "[mock:1] is no longer shown".to_owned(),
+            "revoked mock:1 (current: none, pinned: none, follow: on)".to_owned(),
+        ]));
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "[mock:1] not allowed"));
+    }
+
+    #[test]
+    fn deny_overrides_process_allowance_and_clear_keeps_it() {
+        let outcome = run_timed_scenario(
+            "t=0 focus 11; t=100ms focus 12; t=200ms focus 1",
+            vec![
+                (
+                    Duration::ZERO,
+                    StdinShellMessage::AllowProcess(vec![mock_window_info(mock_window(1))]),
+                ),
+                (
+                    Duration::ZERO,
+                    StdinShellMessage::Deny(vec![mock_window(12)]),
+                ),
+                (Duration::from_millis(150), StdinShellMessage::Clear),
+            ],
+            Duration::from_millis(300),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(11)]);
+        assert!(outcome
+            .shell_output
+            .contains(&"[mock:12] denied".to_owned()));
+        assert!(outcome
+            .shell_output
+            .contains(&"[mock:1] not allowed".to_owned()));
+        assert!(outcome.list.contains("Denied windows:\n| mock:12\n"));
+    }
+
+    fn placeholder_config(placeholder: PlaceholderConfig) -> Config {
+        let mut config = Config::default();
+        config.placeholder = placeholder;
+        config
+    }
+
+    #[test]
+    fn shows_slate_while_disallowed_window_is_focused() {
+        let config = placeholder_config(PlaceholderConfig {
+            policy: PlaceholderPolicy::Slate,
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=200ms focus 3; t=300ms focus 4; t=400ms focus 1",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(600),
+        );
+
+        assert_eq!(
+            outcome.shown,
+            [mock_window(1), placeholder_id(), mock_window(1)]
+        );
+    }
+
+    #[test]
+    fn hold_switches_to_slate_after_timeout() {
+        let config = placeholder_config(PlaceholderConfig {
+            hold_timeout_secs: Some(0.2),
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=100ms focus 3",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(500),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), placeholder_id()]);
+    }
+
+    #[test]
+    fn changing_placeholder_redraws_it() {
+        let outcome = run_timed_scenario(
+            "t=0 focus 1; t=100ms focus 3",
+            vec![
+                (Duration::ZERO, allow(&[1])),
+                (
+                    Duration::from_millis(300),
+                    StdinShellMessage::Placeholder(Some(PlaceholderChange::Policy(
+                        PlaceholderPolicy::Background,
+                    ))),
+                ),
+            ],
+            Duration::from_millis(500),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), placeholder_id()]);
+        assert!(outcome.shell_output.iter().any(|message| message
+            == "placeholder: background, slate \"BE RIGHT BACK\", background #00ff00"));
+    }
+
+    #[test]
+    fn switches_only_to_windows_that_stay_focused() {
+        let mut config = Config::default();
+        config.focus.dwell_ms = 150;
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=300ms focus 2; t=350ms focus 3; t=400ms focus 2",
+            vec![(Duration::ZERO, allow(&[1, 2, 3]))],
+            Duration::from_millis(700),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), mock_window(2)]);
+    }
+
+    #[test]
+    fn ignored_windows_do_not_take_focus() {
+        let mut config = Config::default();
+        config.focus.ignore.push(WindowRule {
+            title: Some(TitlePattern::new("^Mock window 2$").unwrap()),
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=200ms focus 2",
+            vec![
+                (Duration::ZERO, allow(&[1, 2])),
+                (Duration::from_millis(100), StdinShellMessage::Follow(false)),
+                (Duration::from_millis(300), StdinShellMessage::Follow(true)),
+            ],
+            Duration::from_millis(500),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1)]);
+        assert!(!outcome
+            .shell_output
+            .iter()
+            .any(|message| message.starts_with("[mock:2]")));
+    }
+
+    #[test]
+    fn crossfades_between_windows() {
+        let mut config = Config::default();
+        config.transition = TransitionConfig {
+            kind: TransitionKind::Crossfade,
+            duration_ms: 200,
+        };
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=200ms focus 5",
+            vec![(Duration::ZERO, allow(&[1, 5]))],
+            Duration::from_millis(600),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), mock_window(5)]);
+        // モックのフレームはウィンドウ番号で塗られているので、途中の値が混ざった絵になる
+        let values: Vec<_> = outcome
+            .frames
+            .iter()
+            .map(|frame| frame.pixel(0, 0)[0])
+            .collect();
+        assert!(values.iter().any(|&value| 1 < value && value < 5));
+        assert_eq!(values.last(), Some(&5));
+    }
+
+    #[test]
+    fn every_frame_has_canvas_size() {
+        let mut config = Config::default();
+        config.canvas.size = Some("16x9".parse().unwrap());
+        config.placeholder.policy = PlaceholderPolicy::Slate;
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=200ms focus 3",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), placeholder_id()]);
+        assert!(outcome
+            .frames
+            .iter()
+            .all(|frame| (frame.width, frame.height) == (16, 9)));
+    }
+
+    #[test]
+    fn crops_frames_by_rule_and_by_shell() {
+        let mut config = Config::default();
+        config.allow.push(WindowRule {
+            process: Some("mock1".into()),
+            crop: Some("0,0,1,1".parse().unwrap()),
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=200ms focus 2",
+            vec![
+                (Duration::ZERO, allow(&[2])),
+                (
+                    Duration::ZERO,
+                    StdinShellMessage::Crop(
+                        mock_window_info(mock_window(2)),
+                        Some("50%,0,50%,100%".parse().unwrap()),
+                    ),
+                ),
+            ],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), mock_window(2)]);
+        for frame in &outcome.frames {
+            let size = (frame.width, frame.height);
+            if frame.id == mock_window(1) {
+                assert_eq!(size, (1, 1));
+            } else {
+                assert_eq!(size, (1, 2));
+            }
+        }
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message
+                == "[mock:2] crop 50%,0,50%,100% (not saved: no allow rule matches)"));
+    }
+
+    #[test]
+    fn masks_frames_before_cropping() {
+        let mut config = Config::default();
+        config.allow.push(WindowRule {
+            process: Some("mock1".into()),
+            crop: Some("1,0,1,2".parse().unwrap()),
+            masks: vec![Mask {
+                name: "corner".into(),
+                region: "1,1,1,1".parse().unwrap(),
+                style: "#ff0000".parse().unwrap(),
+            }],
+            ..Default::default()
+        });
+        let session_mask = Mask {
+            name: "top".into(),
+            region: "0,0,100%,1".parse().unwrap(),
+            style: "fill".parse().unwrap(),
+        };
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=200ms focus 2",
+            vec![
+                (Duration::ZERO, allow(&[2])),
+                (
+                    Duration::ZERO,
+                    StdinShellMessage::AddMask(mock_window_info(mock_window(2)), session_mask),
+                ),
+            ],
+            Duration::from_millis(400),
+        );
+
+        assert_eq!(outcome.shown, [mock_window(1), mock_window(2)]);
+        for frame in &outcome.frames {
+            if frame.id == mock_window(1) {
+                assert_eq!((frame.width, frame.height), (1, 2));
+                assert_ne!(frame.pixel(0, 0), [255, 0, 0, 255]);
+                assert_eq!(frame.pixel(0, 1), [255, 0, 0, 255]);
+            } else {
+                assert_eq!(frame.pixel(1, 0), [0, 0, 0, 255]);
+                assert_ne!(frame.pixel(1, 1), [0, 0, 0, 255]);
+            }
+        }
+        assert!(outcome.shell_output.iter().any(|message| message
+            == "[mock:2] mask top 0,0,100%,1 fill (not saved: no allow rule matches)"));
+    }
+
+    #[test]
+    fn redacts_window_while_title_matches() {
+        let mut config = Config::default();
+        config.redact.titles = vec![TitlePattern::new("Private").unwrap()];
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=150ms title 1 Private Browsing; t=300ms title 1 Mock window 1",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(450),
+        );
+
+        // 隠していないフレーム、真っ黒なフレーム、また隠していないフレームの順に出る
+        let mut runs: Vec<_> = outcome
+            .frames
+            .iter()
+            .map(|frame| frame.pixel(0, 0) == [0, 0, 0, 255])
+            .collect();
+        runs.dedup();
+        assert_eq!(runs, [false, true, false]);
+        assert_eq!(
+            outcome
+                .shell_output
+                .iter()
+                .filter(|message| message.contains("redacted"))
+                .collect::<Vec<_>>(),
+            [
+                "[mock:1] redacted: title matches /Private/",
+                "[mock:1] no longer redacted"
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_window_when_its_new_title_is_denied() {
+        let mut config = placeholder_config(PlaceholderConfig {
+            policy: PlaceholderPolicy::Slate,
+            ..Default::default()
+        });
+        config.deny.push(WindowRule {
+            title: Some(TitlePattern::new("Private").unwrap()),
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=150ms title 1 Private Browsing",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(300),
+        );
+
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message == "[mock:1] is no longer shown"));
+        assert_eq!(outcome.shown, [mock_window(1), placeholder_id()]);
+    }
+
+    #[test]
+    fn rule_masks_follow_the_current_title() {
+        let mut config = Config::default();
+        config.allow.push(WindowRule {
+            title: Some(TitlePattern::new("Mock window 1").unwrap()),
+            masks: vec![Mask {
+                name: "all".into(),
+                region: "0,0,100%,100%".parse().unwrap(),
+                style: "#ff0000".parse().unwrap(),
+            }],
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=150ms title 1 Inbox; t=300ms title 1 Mock window 1",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(450),
+        );
+
+        // ルールに当てはまる間だけ隠す
+        let mut runs: Vec<_> = outcome
+            .frames
+            .iter()
+            .map(|frame| frame.pixel(0, 0) == [255, 0, 0, 255])
+            .collect();
+        runs.dedup();
+        assert_eq!(runs, [true, false, true]);
+    }
+
+    #[test]
+    fn blackout_hides_output_until_lifted() {
+        let outcome = run_timed_scenario(
+            "t=0 focus 1; t=200ms focus 2",
+            vec![
+                (Duration::ZERO, allow(&[1, 2])),
+                (
+                    Duration::from_millis(100),
+                    StdinShellMessage::Blackout(BlackoutChange::On),
+                ),
+                (
+                    Duration::from_millis(250),
+                    StdinShellMessage::Blackout(BlackoutChange::Toggle),
+                ),
+                (
+                    Duration::from_millis(350),
+                    StdinShellMessage::Blackout(BlackoutChange::Toggle),
+                ),
+            ],
+            Duration::from_millis(400),
+        );
+
+        // ブラックアウト中に切り替わったウィンドウ 2 は、解除されてから出る
+        assert_eq!(
+            outcome.shown,
+            [
+                mock_window(1),
+                placeholder_id(),
+                mock_window(2),
+                placeholder_id()
+            ]
+        );
+        let blackout = outcome.frames.last().unwrap();
+        assert_eq!((blackout.width, blackout.height), (2, 2));
+        assert!(blackout
+            .bytes
+            .chunks(4)
+            .all(|pixel| pixel == [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn lifting_blackout_does_not_fade_from_before_it() {
+        let mut config = Config::default();
+        config.transition = TransitionConfig {
+            kind: TransitionKind::Crossfade,
+            duration_ms: 200,
+        };
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=100ms focus 5; t=300ms focus 3",
+            vec![
+                (Duration::ZERO, allow(&[1, 3, 5])),
+                (
+                    Duration::from_millis(250),
+                    StdinShellMessage::Blackout(BlackoutChange::On),
+                ),
+                (
+                    Duration::from_millis(350),
+                    StdinShellMessage::Blackout(BlackoutChange::Off),
+                ),
+            ],
+            Duration::from_millis(550),
+        );
+
+        // 切り替えの途中でブラックアウトしても、解除したあとはウィンドウ 3 がそのまま出る
+        let values: Vec<_> = outcome
+            .frames
+            .iter()
+            .map(|frame| frame.pixel(0, 0)[0])
+            .collect();
+        let black = values.iter().position(|&value| value == 0).unwrap();
+        assert!(values[..black].iter().any(|&value| 1 < value && value < 5));
+        assert!(values[black + 1..].iter().all(|&value| value == 3));
+    }
+
+    #[test]
+    fn blackout_dumps_delayed_frames() {
+        let mut config = Config::default();
+        config.delay.secs = 0.15;
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1",
+            vec![
+                (Duration::ZERO, allow(&[1])),
+                (
+                    Duration::from_millis(100),
+                    StdinShellMessage::Blackout(BlackoutChange::On),
+                ),
+                (
+                    Duration::from_millis(200),
+                    StdinShellMessage::Blackout(BlackoutChange::Off),
+                ),
+            ],
+            Duration::from_millis(500),
+        );
+
+        // ブラックアウトまでのフレームはまだ遅延の中にあったので、ひとつも出ない
+        assert_eq!(outcome.shown, [placeholder_id(), mock_window(1)]);
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message.starts_with("dumped ") && message.ends_with(" delayed frames")));
+    }
+
+    #[test]
+    fn revoking_drops_delayed_frames_of_the_window() {
+        let mut config = placeholder_config(PlaceholderConfig {
+            policy: PlaceholderPolicy::Slate,
+            ..Default::default()
+        });
+        config.delay.secs = 0.15;
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1",
+            vec![
+                (Duration::ZERO, allow(&[1])),
+                (
+                    Duration::from_millis(100),
+                    StdinShellMessage::Revoke(vec![mock_window_info(mock_window(1))]),
+                ),
+            ],
+            Duration::from_millis(400),
+        );
+
+        // 取り消す前のフレームはまだ遅延の中にあったので、ひとつも出ない
+        assert_eq!(outcome.shown, [placeholder_id()]);
+        assert!(outcome
+            .shell_output
+            .iter()
+            .any(|message| message.starts_with("[mock:1] dropped ")
+                && message.ends_with(" delayed frames")));
+    }
+
+    #[test]
+    fn redaction_drops_delayed_frames_of_the_window() {
+        let mut config = Config::default();
+        config.redact.titles = vec![TitlePattern::new("Private").unwrap()];
+        config.delay.secs = 0.15;
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1; t=300ms title 1 Private Browsing",
+            vec![(Duration::ZERO, allow(&[1]))],
+            Duration::from_millis(600),
+        );
+
+        // タイトルが変わる前に撮って遅延の中にあったフレームも、隠さずには出さない
+        let mut runs: Vec<_> = outcome
+            .frames
+            .iter()
+            .filter(|frame| frame.id == mock_window(1))
+            .map(|frame| frame.pixel(0, 0) == [0, 0, 0, 255])
+            .collect();
+        let unredacted = runs.iter().filter(|redacted| !**redacted).count();
+        runs.dedup();
+        assert_eq!(runs, [false, true]);
+        // 隠さずに出るのは、遅延の 150ms が過ぎてからタイトルが変わる 300ms までに出したものだけ
+        assert!(unredacted as u128 <= 150 / STEP.as_millis() + 1);
+    }
+
+    #[test]
+    fn pinning_right_after_revoke_restarts_the_capture() {
+        let config = placeholder_config(PlaceholderConfig {
+            policy: PlaceholderPolicy::Slate,
+            ..Default::default()
+        });
+        let outcome = run_configured_scenario(
+            config,
+            "t=0 focus 1",
+            vec![
+                (Duration::ZERO, allow(&[1])),
+                (
+                    Duration::from_millis(100),
+                    StdinShellMessage::Revoke(vec![mock_window_info(mock_window(1))]),
+                ),
+                (
+                    Duration::from_millis(100),
+                    StdinShellMessage::Pin(mock_window_info(mock_window(1))),
+                ),
+            ],
+            Duration::from_millis(400),
+        );
+
+        // 止めたスレッドが終わるのを待たずに、新しいキャプチャからフレームが届き続ける
+        assert_eq!(
+            outcome.shown,
+            [mock_window(1), placeholder_id(), mock_window(1)]
+        );
+        let resumed = outcome
+            .frames
+            .iter()
+            .rev()
+            .take_while(|frame| frame.id == mock_window(1))
+            .count();
+        assert!(resumed > 10, "{resumed}");
     }
 }
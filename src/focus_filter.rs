@@ -0,0 +1,183 @@
+//! 前面ウィンドウの変化のうち、配信に出すまでもないものを取り除く。
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::WindowRule, window_id::WindowId, window_list::WindowInfo};
+
+// タスク切り替えや通知、ランチャーなど、一瞬だけ前面に来るシステムのウィンドウ
+const TRANSIENT_CLASSES: &str = r#"class in [
+    "MultitaskingViewFrame", "XamlExplorerHostIslandWindow", "ForegroundStaging",
+    "TaskSwitcherWnd", "TaskListThumbnailWnd", "Shell_TrayWnd", "Shell_SecondaryTrayWnd",
+    "NotifyIconOverflowWindow", "Windows.UI.Core.CoreWindow",
+    "Rofi", "dmenu", "Ulauncher", "krunner", "albert", "Dunst", "Xfce4-notifyd", "Plank",
+]"#;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FocusConfig {
+    // この時間だけ前面にいたウィンドウに切り替える。0 ならすぐ切り替える
+    pub dwell_ms: u64,
+    // これに当てはまるウィンドウが前面に来ても無視する
+    pub ignore: Vec<WindowRule>,
+}
+
+impl Default for FocusConfig {
+    fn default() -> Self {
+        Self {
+            dwell_ms: 0,
+            ignore: vec![WindowRule {
+                when: Some(TRANSIENT_CLASSES.parse().unwrap()),
+                ..Default::default()
+            }],
+        }
+    }
+}
+
+impl FocusConfig {
+    pub fn dwell(&self) -> Duration {
+        Duration::from_millis(self.dwell_ms)
+    }
+
+    pub fn ignores(&self, info: &WindowInfo) -> bool {
+        self.ignore.iter().any(|rule| rule.matches(info))
+    }
+}
+
+/// 前面に来てから一定時間たったウィンドウだけを通す。時刻は呼び出し側が渡す。
+#[derive(Default)]
+pub struct FocusDebouncer {
+    // 最後に通したウィンドウ
+    settled: Option<WindowId>,
+    pending: Option<(WindowInfo, Instant)>,
+}
+
+impl FocusDebouncer {
+    /// すぐに切り替えてよいならそのウィンドウを返す
+    pub fn on_focus(
+        &mut self,
+        info: WindowInfo,
+        dwell: Duration,
+        now: Instant,
+    ) -> Option<WindowInfo> {
+        // すぐに元のウィンドウへ戻ってきたなら何も変わっていない
+        if self.settled == Some(info.id) {
+            self.pending = None;
+            return None;
+        }
+
+        if dwell.is_zero() {
+            self.pending = None;
+            self.settled = Some(info.id);
+            return Some(info);
+        }
+
+        self.pending = Some((info, now + dwell));
+        None
+    }
+
+    /// 待っているウィンドウを切り替えてよい時刻
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|(_, deadline)| *deadline)
+    }
+
+    /// 時間がたって切り替えてよくなったウィンドウを返す
+    pub fn poll(&mut self, now: Instant) -> Option<WindowInfo> {
+        if self.deadline().is_some_and(|deadline| deadline <= now) {
+            let (info, _) = self.pending.take().unwrap();
+            self.settled = Some(info.id);
+            return Some(info);
+        }
+
+        None
+    }
+
+    /// 通したウィンドウが閉じられたら、次に前面に来たときにまた通す
+    pub fn forget(&mut self, id: WindowId) {
+        if self.settled == Some(id) {
+            self.settled = None;
+        }
+        if self.pending.as_ref().is_some_and(|(info, _)| info.id == id) {
+            self.pending = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{mock_window, mock_window_info};
+
+    const DWELL: Duration = Duration::from_millis(300);
+
+    fn info(n: u64) -> WindowInfo {
+        mock_window_info(mock_window(n))
+    }
+
+    fn settled(debouncer: &mut FocusDebouncer, now: Instant) -> Option<u64> {
+        debouncer.poll(now).map(|info| info.id.raw())
+    }
+
+    #[test]
+    fn switches_only_after_dwell_time() {
+        let t0 = Instant::now();
+        let ms = |ms| t0 + Duration::from_millis(ms);
+        let mut debouncer = FocusDebouncer::default();
+
+        assert!(debouncer.on_focus(info(1), DWELL, t0).is_none());
+        assert_eq!(debouncer.deadline(), Some(ms(300)));
+        assert_eq!(settled(&mut debouncer, ms(299)), None);
+        assert_eq!(settled(&mut debouncer, ms(300)), Some(1));
+        assert_eq!(debouncer.deadline(), None);
+    }
+
+    #[test]
+    fn windows_passed_through_quickly_are_skipped() {
+        let t0 = Instant::now();
+        let ms = |ms| t0 + Duration::from_millis(ms);
+        let mut debouncer = FocusDebouncer::default();
+        debouncer.on_focus(info(1), DWELL, t0);
+        debouncer.poll(ms(300));
+
+        // alt-tab で 2 と 3 を通り過ぎて 4 で止まる
+        debouncer.on_focus(info(2), DWELL, ms(1000));
+        debouncer.on_focus(info(3), DWELL, ms(1100));
+        debouncer.on_focus(info(4), DWELL, ms(1200));
+        assert_eq!(settled(&mut debouncer, ms(1350)), None);
+        assert_eq!(settled(&mut debouncer, ms(1500)), Some(4));
+
+        // 通り過ぎて元に戻ってきたら何もしない
+        debouncer.on_focus(info(2), DWELL, ms(2000));
+        assert!(debouncer.on_focus(info(4), DWELL, ms(2100)).is_none());
+        assert_eq!(debouncer.deadline(), None);
+    }
+
+    #[test]
+    fn zero_dwell_switches_immediately() {
+        let mut debouncer = FocusDebouncer::default();
+        let now = Instant::now();
+
+        let info = debouncer.on_focus(info(1), Duration::ZERO, now).unwrap();
+        assert_eq!(info.id, mock_window(1));
+        assert!(debouncer.on_focus(info, Duration::ZERO, now).is_none());
+
+        debouncer.forget(mock_window(1));
+        assert!(debouncer
+            .on_focus(mock_window_info(mock_window(1)), Duration::ZERO, now)
+            .is_some());
+    }
+
+    #[test]
+    fn ignores_transient_system_windows() {
+        let config = FocusConfig::default();
+        let window = |class: &str| WindowInfo {
+            class: class.into(),
+            ..info(1)
+        };
+
+        assert!(config.ignores(&window("MultitaskingViewFrame")));
+        assert!(config.ignores(&window("Rofi")));
+        assert!(!config.ignores(&window("MockWindow")));
+    }
+}
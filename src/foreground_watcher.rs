@@ -1,12 +1,16 @@
-use std::{thread, time::Duration};
-
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use windows::Win32::{Foundation::HWND, UI::WindowsAndMessaging::GetForegroundWindow};
+
+use crate::{window_id::WindowId, window_list::WindowInfo};
+
+#[cfg(windows)]
+mod win32;
+#[cfg(unix)]
+mod x11;
 
 pub struct ForegroundWatcher {
     rx_cmd: Receiver<ForegroundWatcherCommand>,
     tx_msg: Sender<ForegroundWatcherMessage>,
-    old_hwnd: Option<HWND>,
+    old_id: Option<WindowId>,
 }
 
 pub enum ForegroundWatcherCommand {
@@ -14,7 +18,8 @@ pub enum ForegroundWatcherCommand {
 }
 
 pub enum ForegroundWatcherMessage {
-    WindowChanged { hwnd: HWND },
+    WindowChanged { info: WindowInfo },
+    Output { message: String },
 }
 
 impl ForegroundWatcher {
@@ -30,7 +35,7 @@ impl ForegroundWatcher {
             Self {
                 rx_cmd,
                 tx_msg,
-                old_hwnd: None,
+                old_id: None,
             },
             tx_cmd,
             rx_msg,
@@ -38,22 +43,32 @@ impl ForegroundWatcher {
     }
 
     pub fn run(mut self) {
-        loop {
-            if let Ok(msg) = self.rx_cmd.try_recv() {
-                match msg {
-                    ForegroundWatcherCommand::Quit => break,
-                }
-            }
-
-            let hwnd = unsafe { GetForegroundWindow() };
-            if Some(hwnd) != self.old_hwnd {
-                self.old_hwnd = Some(hwnd);
-                let _ = self
-                    .tx_msg
-                    .send(ForegroundWatcherMessage::WindowChanged { hwnd });
-            }
-
-            thread::sleep(Duration::from_millis(100));
+        #[cfg(windows)]
+        let res = win32::run(&mut self);
+        #[cfg(unix)]
+        let res = x11::run(&mut self);
+
+        if let Err(e) = res {
+            self.output(format!("foreground watcher stopped: {e}"));
+        }
+    }
+
+    fn output(&self, message: String) {
+        let _ = self
+            .tx_msg
+            .send(ForegroundWatcherMessage::Output { message });
+    }
+
+    /// query はウィンドウが変わったときだけ呼ぶ
+    fn notify(&mut self, id: WindowId, query: impl FnOnce(WindowId) -> Result<WindowInfo, String>) {
+        if Some(id) != self.old_id {
+            self.old_id = Some(id);
+
+            // ルールで判定できるよう、タイトルなども一緒に送る
+            let info = query(id).unwrap_or_else(|_| WindowInfo::unknown(id));
+            let _ = self
+                .tx_msg
+                .send(ForegroundWatcherMessage::WindowChanged { info });
         }
     }
 }
@@ -0,0 +1,23 @@
+use std::{thread, time::Duration};
+
+use windows::Win32::UI::WindowsAndMessaging::GetForegroundWindow;
+
+use super::{ForegroundWatcher, ForegroundWatcherCommand};
+use crate::{window_id::WindowId, window_list};
+
+pub(super) fn run(watcher: &mut ForegroundWatcher) -> Result<(), String> {
+    loop {
+        if let Ok(msg) = watcher.rx_cmd.try_recv() {
+            match msg {
+                ForegroundWatcherCommand::Quit => break,
+            }
+        }
+
+        let hwnd = unsafe { GetForegroundWindow() };
+        watcher.notify(WindowId::from_hwnd(hwnd), window_list::window_info);
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Ok(())
+}
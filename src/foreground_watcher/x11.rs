@@ -0,0 +1,147 @@
+use std::{error::Error, sync::Arc, thread};
+
+use crossbeam_channel::{select, unbounded};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        xproto::{
+            AtomEnum, ChangeWindowAttributesAux, ClientMessageEvent, ConnectionExt as _,
+            CreateWindowAux, EventMask, Window, WindowClass,
+        },
+        Event,
+    },
+    rust_connection::RustConnection,
+};
+
+use super::{ForegroundWatcher, ForegroundWatcherCommand};
+use crate::{window_id::WindowId, window_list::WindowQuery};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_ACTIVE_WINDOW,
+    }
+}
+
+pub(super) fn run(watcher: &mut ForegroundWatcher) -> Result<(), String> {
+    let (conn, screen_num) = x11rb::connect(None).map_err(|e| e.to_string())?;
+    let conn = Arc::new(conn);
+    let root = conn.setup().roots[screen_num].root;
+    let atoms = Atoms::new(&*conn)
+        .map_err(|e| e.to_string())?
+        .reply()
+        .map_err(|e| e.to_string())?;
+    // フォーカスが変わるたびに接続し直さず、同じ接続でウィンドウを調べる
+    let query = WindowQuery::new(&conn)?;
+
+    // ルートウィンドウのプロパティ変更を購読して、_NET_ACTIVE_WINDOW の変化を待つ
+    conn.change_window_attributes(
+        root,
+        &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+    )
+    .map_err(|e| e.to_string())?
+    .check()
+    .map_err(|e| e.to_string())?;
+
+    // 終了するときにイベント待ちのスレッドを起こすため、自分宛てのイベントを送る先
+    let wake_window = create_wake_window(&conn, root).map_err(|e| e.to_string())?;
+
+    let notify_active_window = |watcher: &mut ForegroundWatcher| {
+        // 調べている間にウィンドウが消えることもあるので、失敗しても見張り続ける
+        match active_window(&conn, root, &atoms) {
+            Ok(Some(window)) => watcher.notify(WindowId::from_x11(window), |id| {
+                query.window_info(&conn, id)
+            }),
+            Ok(None) => {}
+            Err(e) => watcher.output(format!("failed to get the active window: {e}")),
+        }
+    };
+    notify_active_window(watcher);
+
+    // wait_for_event はブロックするので、終了要求と同時に待てるよう別スレッドからチャンネルに流す
+    let (tx_event, rx_event) = unbounded();
+    let event_conn = Arc::clone(&conn);
+    let event_thread = thread::spawn(move || {
+        while let Ok(event) = event_conn.wait_for_event() {
+            if tx_event.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        select! {
+            recv(watcher.rx_cmd) -> msg => match msg {
+                Ok(ForegroundWatcherCommand::Quit) | Err(_) => break,
+            },
+            recv(rx_event) -> event => {
+                let Ok(event) = event else {
+                    return Err("connection to the X server is lost".into());
+                };
+
+                if let Event::PropertyNotify(ev) = event {
+                    if ev.window == root && ev.atom == atoms._NET_ACTIVE_WINDOW {
+                        notify_active_window(watcher);
+                    }
+                }
+            }
+        }
+    }
+
+    // 受け取り側を閉じてから起こすと、スレッドは送れずに抜ける
+    drop(rx_event);
+    let wake = ClientMessageEvent::new(32, wake_window, AtomEnum::NONE, [0u32; 5]);
+    if conn
+        .send_event(false, wake_window, EventMask::NO_EVENT, wake)
+        .and_then(|_| conn.flush())
+        .is_ok()
+    {
+        let _ = event_thread.join();
+    }
+
+    Ok(())
+}
+
+/// 見えない InputOnly のウィンドウ
+fn create_wake_window(conn: &RustConnection, root: Window) -> Result<Window, Box<dyn Error>> {
+    let window = conn.generate_id()?;
+    conn.create_window(
+        0,
+        window,
+        root,
+        0,
+        0,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_ONLY,
+        x11rb::COPY_FROM_PARENT,
+        &CreateWindowAux::new(),
+    )?
+    .check()?;
+    Ok(window)
+}
+
+fn active_window(
+    conn: &RustConnection,
+    root: Window,
+    atoms: &Atoms,
+) -> Result<Option<Window>, String> {
+    let reply = conn
+        .get_property(
+            false,
+            root,
+            atoms._NET_ACTIVE_WINDOW,
+            AtomEnum::WINDOW,
+            0,
+            1,
+        )
+        .map_err(|e| e.to_string())?
+        .reply()
+        .map_err(|e| e.to_string())?;
+
+    // フォーカスされたウィンドウがないときは 0 (None) が入っている
+    Ok(reply
+        .value32()
+        .and_then(|mut values| values.next())
+        .filter(|&window| window != x11rb::NONE))
+}
@@ -0,0 +1,143 @@
+//! キャプチャから最新のフレームだけを受け渡すための 1 枠の郵便受け。
+//!
+//! 送り手は決してブロックせず、受け手が取り出す前に次のフレームが届いたら古いほうは捨てられる。
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+use crate::window_capture::CapturedFrame;
+
+struct Shared {
+    slot: Mutex<Option<CapturedFrame>>,
+    posted: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MailboxStats {
+    pub posted: u64,
+    pub dropped: u64,
+}
+
+pub struct FramePoster {
+    shared: Arc<Shared>,
+    tx_ready: Sender<()>,
+}
+
+pub struct FrameMailbox {
+    shared: Arc<Shared>,
+    rx_ready: Receiver<()>,
+}
+
+pub fn frame_mailbox() -> (FramePoster, FrameMailbox) {
+    let shared = Arc::new(Shared {
+        slot: Mutex::new(None),
+        posted: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+    });
+    // 通知は「何か届いている」ことさえ伝わればよいので 1 つで十分
+    let (tx_ready, rx_ready) = bounded(1);
+
+    (
+        FramePoster {
+            shared: Arc::clone(&shared),
+            tx_ready,
+        },
+        FrameMailbox { shared, rx_ready },
+    )
+}
+
+impl FramePoster {
+    pub fn post(&self, frame: CapturedFrame) {
+        let old = self.shared.slot.lock().unwrap().replace(frame);
+        if old.is_some() {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.shared.posted.fetch_add(1, Ordering::Relaxed);
+
+        let _ = self.tx_ready.try_send(());
+    }
+}
+
+impl FrameMailbox {
+    /// フレームが届くと ready になるチャンネル。送り手がいなくなると切断される。
+    pub fn ready(&self) -> &Receiver<()> {
+        &self.rx_ready
+    }
+
+    pub fn take(&self) -> Option<CapturedFrame> {
+        let _ = self.rx_ready.try_recv();
+        self.shared.slot.lock().unwrap().take()
+    }
+
+    pub fn stats(&self) -> MailboxStats {
+        MailboxStats {
+            posted: self.shared.posted.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{mock::mock_window, window_capture::PixelFormat};
+
+    fn frame(n: u8) -> CapturedFrame {
+        CapturedFrame {
+            id: mock_window(1),
+            width: 1,
+            height: 1,
+            stride: 4,
+            format: PixelFormat::Rgba8,
+            timestamp: Instant::now(),
+            bytes: vec![n; 4].into(),
+            client_area: None,
+        }
+    }
+
+    #[test]
+    fn keeps_only_latest_frame() {
+        let (poster, mailbox) = frame_mailbox();
+        poster.post(frame(1));
+        poster.post(frame(2));
+        poster.post(frame(3));
+
+        assert_eq!(*mailbox.take().unwrap().bytes, [3; 4]);
+        assert!(mailbox.take().is_none());
+        assert_eq!(
+            mailbox.stats(),
+            MailboxStats {
+                posted: 3,
+                dropped: 2
+            }
+        );
+    }
+
+    #[test]
+    fn signals_readiness_until_taken() {
+        let (poster, mailbox) = frame_mailbox();
+        assert!(mailbox.ready().is_empty());
+
+        poster.post(frame(1));
+        poster.post(frame(2));
+        assert_eq!(mailbox.ready().len(), 1);
+
+        mailbox.take();
+        assert!(mailbox.ready().is_empty());
+    }
+
+    #[test]
+    fn disconnects_when_poster_is_dropped() {
+        let (poster, mailbox) = frame_mailbox();
+        drop(poster);
+
+        assert!(mailbox.ready().recv().is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! フレーム用バッファの使い回し。
+//!
+//! 確保したバッファは `FrameBuffer` として凍結すると参照カウントで共有でき、最後の参照が消えたときに
+//! プールへ返却される。複数の出力先に配ってもコピーは発生しない。
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+struct PoolInner {
+    free: Mutex<Vec<Vec<u8>>>,
+    capacity: usize,
+}
+
+#[derive(Clone)]
+pub struct FramePool {
+    inner: Arc<PoolInner>,
+}
+
+impl FramePool {
+    /// `capacity` は手元に残しておく空きバッファの最大数
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                free: Mutex::new(vec![]),
+                capacity,
+            }),
+        }
+    }
+
+    /// `len` バイトのバッファを借りる。中身は以前のフレームの残りかもしれない。
+    pub fn acquire(&self, len: usize) -> FrameBufferMut {
+        let mut bytes = self.inner.free.lock().unwrap().pop().unwrap_or_default();
+        bytes.resize(len, 0);
+
+        FrameBufferMut {
+            bytes,
+            pool: Some(Arc::clone(&self.inner)),
+        }
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.inner.free.lock().unwrap().len()
+    }
+}
+
+/// 書き込み中のバッファ。書き終えたら `freeze` して共有する。
+pub struct FrameBufferMut {
+    bytes: Vec<u8>,
+    pool: Option<Arc<PoolInner>>,
+}
+
+impl FrameBufferMut {
+    pub fn freeze(self) -> FrameBuffer {
+        FrameBuffer(Arc::new(PooledBytes {
+            bytes: self.bytes,
+            pool: self.pool,
+        }))
+    }
+}
+
+impl Deref for FrameBufferMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl DerefMut for FrameBufferMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+struct PooledBytes {
+    bytes: Vec<u8>,
+    pool: Option<Arc<PoolInner>>,
+}
+
+impl Drop for PooledBytes {
+    fn drop(&mut self) {
+        let Some(pool) = &self.pool else {
+            return;
+        };
+
+        let mut free = pool.free.lock().unwrap();
+        if free.len() < pool.capacity {
+            free.push(std::mem::take(&mut self.bytes));
+        }
+    }
+}
+
+/// 読み取り専用で共有されるフレームのバッファ。clone してもバイト列はコピーされない。
+#[derive(Clone)]
+pub struct FrameBuffer(Arc<PooledBytes>);
+
+impl FrameBuffer {
+    pub fn ptr_eq(&self, other: &FrameBuffer) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Deref for FrameBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0.bytes
+    }
+}
+
+impl From<FrameBufferMut> for FrameBuffer {
+    fn from(buf: FrameBufferMut) -> Self {
+        buf.freeze()
+    }
+}
+
+/// プールに属さないバッファ。破棄されるとそのまま解放される。
+impl From<Vec<u8>> for FrameBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        FrameBuffer(Arc::new(PooledBytes { bytes, pool: None }))
+    }
+}
+
+impl fmt::Debug for FrameBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameBuffer")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        hint::black_box,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    #[test]
+    fn returns_buffer_to_pool_after_last_reference() {
+        let pool = FramePool::new(2);
+        let buf = pool.acquire(16).freeze();
+        let shared = buf.clone();
+
+        drop(buf);
+        assert_eq!(pool.free_count(), 0);
+        drop(shared);
+        assert_eq!(pool.free_count(), 1);
+    }
+
+    #[test]
+    fn reuses_returned_allocation() {
+        let pool = FramePool::new(2);
+        let buf = pool.acquire(16);
+        let ptr = buf.as_ptr();
+        drop(buf.freeze());
+
+        let buf = pool.acquire(16);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(buf.len(), 16);
+    }
+
+    #[test]
+    fn keeps_at_most_capacity_buffers() {
+        let pool = FramePool::new(1);
+        let a = pool.acquire(4).freeze();
+        let b = pool.acquire(4).freeze();
+        drop(a);
+        drop(b);
+
+        assert_eq!(pool.free_count(), 1);
+    }
+
+    #[test]
+    fn clones_share_bytes() {
+        let mut buf = FramePool::new(1).acquire(4);
+        buf.copy_from_slice(&[1, 2, 3, 4]);
+        let buf = buf.freeze();
+        let other = buf.clone();
+
+        assert!(buf.ptr_eq(&other));
+        assert_eq!(&*other, &[1, 2, 3, 4]);
+    }
+
+    /// 1 フレームあたり新しく Vec を確保して行ごとにコピーし、出力先の数だけ複製する従来の方法と、
+    /// プールから借りて Arc で配る方法を比べる。
+    ///
+    /// `cargo test --release bench_frame_paths -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_frame_paths() {
+        const FRAMES: usize = 60;
+        const CONSUMERS: usize = 3;
+
+        for (name, width, height) in [("1080p", 1920, 1080), ("4K", 3840, 2160)] {
+            // キャプチャ元は幅が 32 の倍数に切り上げられたバッファ
+            let stride = usize::div_ceil(width, 32) * 32 * 4;
+            let source = vec![0x80u8; stride * height];
+            let row_len = width * 4;
+
+            let vec_path = measure(FRAMES, || {
+                let mut bytes = Vec::with_capacity(row_len * height);
+                for row in source.chunks_exact(stride) {
+                    bytes.extend_from_slice(&row[..row_len]);
+                }
+                let copies: Vec<_> = (0..CONSUMERS).map(|_| bytes.clone()).collect();
+                black_box(copies);
+            });
+
+            let pool = FramePool::new(4);
+            let pooled_path = measure(FRAMES, || {
+                let mut bytes = pool.acquire(row_len * height);
+                for (dst, row) in bytes
+                    .chunks_exact_mut(row_len)
+                    .zip(source.chunks_exact(stride))
+                {
+                    dst.copy_from_slice(&row[..row_len]);
+                }
+                let bytes = bytes.freeze();
+                let copies: Vec<_> = (0..CONSUMERS).map(|_| bytes.clone()).collect();
+                black_box(copies);
+            });
+
+            println!(
+                "{name} x{FRAMES} frames, {CONSUMERS} consumers: vec {:.2} ms/frame, pooled {:.2} ms/frame (budget {:.2} ms at 60fps)",
+                per_frame_ms(vec_path, FRAMES),
+                per_frame_ms(pooled_path, FRAMES),
+                1000.0 / 60.0,
+            );
+        }
+    }
+
+    fn measure(frames: usize, mut f: impl FnMut()) -> Duration {
+        // 最初の数フレームはプールが温まっていないので除外する
+        for _ in 0..4 {
+            f();
+        }
+
+        let start = Instant::now();
+        for _ in 0..frames {
+            f();
+        }
+        start.elapsed()
+    }
+
+    fn per_frame_ms(elapsed: Duration, frames: usize) -> f64 {
+        elapsed.as_secs_f64() * 1000.0 / frames as f64
+    }
+}
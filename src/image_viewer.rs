@@ -53,8 +53,22 @@ impl ImageViewer {
         self.is_running = true;
 
         while self.is_running {
-            if let Ok(cmd) = self.rx_cmd.recv() {
-                self.handle_command(&window, cmd);
+            let Ok(cmd) = self.rx_cmd.recv() else {
+                break;
+            };
+
+            // 描画が追いつかずに溜まった更新は、最新のもの以外を飛ばす
+            let pending: Vec<_> = self.rx_cmd.try_iter().collect();
+            let mut latest_update = None;
+            for cmd in [cmd].into_iter().chain(pending) {
+                match cmd {
+                    ImageViewerCommand::Update(frame) => latest_update = Some(frame),
+                    cmd => self.handle_command(&window, cmd),
+                }
+            }
+
+            if let Some(frame) = latest_update {
+                self.handle_command(&window, ImageViewerCommand::Update(frame));
             }
         }
     }
@@ -62,6 +76,7 @@ impl ImageViewer {
     fn handle_command(&mut self, window: &WindowProxy, command: ImageViewerCommand) {
         match command {
             ImageViewerCommand::Update(frame) => {
+                let frame = frame.to_rgba8();
                 let image =
                     ImageView::new(ImageInfo::rgba8(frame.width, frame.height), &frame.bytes);
                 if window.set_image("capture", image).is_err() {
@@ -1,18 +1,54 @@
-use std::thread::{self};
+use std::{
+    env,
+    thread::{self},
+};
 
 use crate::{
-    driver::Driver, foreground_watcher::ForegroundWatcher, image_viewer::ImageViewer,
+    config::Config,
+    control::{default_socket_path, ControlServer},
+    driver::Driver,
+    foreground_watcher::ForegroundWatcher,
+    image_viewer::ImageViewer,
     stdin_shell::StdinShell,
 };
 
+pub mod canvas;
+pub mod config;
+pub mod control;
+pub mod crop;
+pub mod delay_line;
+pub mod draw;
 pub mod driver;
+pub mod focus_filter;
 pub mod foreground_watcher;
+pub mod frame_mailbox;
+pub mod frame_pool;
 pub mod image_viewer;
+pub mod mask;
+#[cfg(test)]
+mod mock;
+pub mod placeholder;
+pub mod redact;
+pub mod replay;
+pub mod rule_expr;
 pub mod stdin_shell;
+pub mod transition;
 pub mod window_capture;
+pub mod window_id;
+pub mod window_list;
 
 #[show_image::main]
 fn main() {
+    // 設定ファイルは引数で指定できる
+    let config_path = env::args().nth(1);
+    let config = match Config::load(config_path.as_deref().unwrap_or(config::DEFAULT_PATH)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("failed to load config: {e}");
+            return;
+        }
+    };
+
     let (viewer, im_tx_cmd, im_rx_msg) = ImageViewer::new();
     let viewer = thread::spawn(move || viewer.run());
 
@@ -22,9 +58,20 @@ fn main() {
     let (shell, sh_tx_cmd, sh_rx_msg) = StdinShell::new();
     let shell = thread::spawn(move || shell.run());
 
+    let (control, ctl_tx_cmd, ctl_rx_msg) = ControlServer::new(default_socket_path());
+    let control = thread::spawn(move || control.run());
+
     let driver = Driver::new(
-        im_tx_cmd, im_rx_msg, fw_tx_cmd, fw_rx_msg, sh_tx_cmd, sh_rx_msg,
-    );
+        im_tx_cmd,
+        im_rx_msg,
+        fw_tx_cmd,
+        fw_rx_msg,
+        sh_tx_cmd,
+        sh_rx_msg,
+        Box::new(crate::window_capture::default_backend),
+    )
+    .with_config(config)
+    .with_control(ctl_tx_cmd, ctl_rx_msg);
 
     driver.run();
     eprintln!("driver finished");
@@ -35,4 +82,6 @@ fn main() {
     eprintln!("watcher finished");
     shell.join().unwrap();
     eprintln!("shell finished");
+    control.join().unwrap();
+    eprintln!("control finished");
 }
@@ -0,0 +1,196 @@
+//! ウィンドウの中の見せたくないところを隠す。
+
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    crop::Region,
+    draw::{self, Color},
+    window_capture::{CapturedFrame, PixelFormat},
+};
+
+/// 名前の付いた隠す範囲。範囲はウィンドウ全体 (切り抜く前) の左上が原点
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mask {
+    pub name: String,
+    pub region: Region,
+    #[serde(default)]
+    pub style: MaskStyle,
+}
+
+/// `fill` (黒) か `#rrggbb` で塗りつぶす、`pixelate` でモザイク、`blur` でぼかす
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaskStyle {
+    Fill(Color),
+    Pixelate,
+    Blur,
+}
+
+impl Default for MaskStyle {
+    fn default() -> Self {
+        MaskStyle::Fill(Color::BLACK)
+    }
+}
+
+impl Mask {
+    /// 詰め物のない RGBA8 のバッファに描く
+    fn apply(&self, bytes: &mut [u8], width: u32, height: u32) {
+        let Some(rect) = self.region.rect(width, height) else {
+            return;
+        };
+        let rect = (rect.x, rect.y, rect.width, rect.height);
+
+        // 細かいところが読めないよう、大きな画面ほど粗くする
+        let coarseness = (width.max(height) / 80).max(8);
+        match self.style {
+            MaskStyle::Fill(color) => draw::fill_rect(bytes, width, height, rect, color.0),
+            MaskStyle::Pixelate => draw::pixelate(bytes, width, height, rect, coarseness),
+            MaskStyle::Blur => draw::blur_rect(bytes, width, height, rect, coarseness),
+        }
+    }
+}
+
+/// masks をすべて掛けたフレームを返す。何もなければコピーせずにそのまま返す
+pub fn apply_masks<'a>(
+    frame: CapturedFrame,
+    masks: impl IntoIterator<Item = &'a Mask>,
+) -> CapturedFrame {
+    let mut masks = masks.into_iter().peekable();
+    if masks.peek().is_none() {
+        return frame;
+    }
+
+    let (width, height) = (frame.width, frame.height);
+    let mut bytes = vec![0; (width * height) as usize * 4];
+    frame.write_rgba8(&mut bytes);
+    for mask in masks {
+        mask.apply(&mut bytes, width, height);
+    }
+
+    CapturedFrame {
+        stride: width as usize * 4,
+        format: PixelFormat::Rgba8,
+        bytes: bytes.into(),
+        ..frame
+    }
+}
+
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.name, self.region, self.style)
+    }
+}
+
+impl fmt::Display for MaskStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskStyle::Fill(color) if *color == Color::BLACK => f.write_str("fill"),
+            MaskStyle::Fill(color) => write!(f, "{color}"),
+            MaskStyle::Pixelate => f.write_str("pixelate"),
+            MaskStyle::Blur => f.write_str("blur"),
+        }
+    }
+}
+
+impl FromStr for MaskStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fill" => Ok(MaskStyle::Fill(Color::BLACK)),
+            "pixelate" => Ok(MaskStyle::Pixelate),
+            "blur" => Ok(MaskStyle::Blur),
+            s => s.parse().map(MaskStyle::Fill).map_err(|_| {
+                format!("unknown mask style {s}, expected fill, #rrggbb, pixelate or blur")
+            }),
+        }
+    }
+}
+
+impl Serialize for MaskStyle {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MaskStyle {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::mock::mock_window;
+
+    fn frame(width: u32, height: u32) -> CapturedFrame {
+        // 横方向に明るくなるグラデーションの BGRX
+        let bytes = (0..width * height)
+            .flat_map(|i| {
+                let v = (i % width * 255 / (width - 1)) as u8;
+                [v, v, v, 0]
+            })
+            .collect::<Vec<_>>();
+        CapturedFrame {
+            id: mock_window(1),
+            width,
+            height,
+            stride: width as usize * 4,
+            format: PixelFormat::Bgrx8,
+            timestamp: Instant::now(),
+            bytes: bytes.into(),
+            client_area: None,
+        }
+    }
+
+    fn mask(region: &str, style: &str) -> Mask {
+        Mask {
+            name: "test".into(),
+            region: region.parse().unwrap(),
+            style: style.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn fill_covers_only_region() {
+        let masked = apply_masks(frame(64, 8), &[mask("0,0,50%,100%", "#ff0000")]);
+
+        assert_eq!(masked.pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(masked.pixel(31, 7), [255, 0, 0, 255]);
+        assert_eq!(masked.pixel(63, 0), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn pixelate_and_blur_hide_detail() {
+        for style in ["pixelate", "blur"] {
+            let original = frame(64, 8);
+            let masked = apply_masks(original.clone(), &[mask("0,0,16,8", style)]);
+
+            // 隠した範囲では隣り合う画素の差がなくなるか小さくなる
+            let step = |frame: &CapturedFrame| frame.pixel(4, 4)[0].abs_diff(frame.pixel(3, 4)[0]);
+            assert!(step(&masked) < step(&original), "{style}");
+            assert_eq!(masked.pixel(40, 4), original.pixel(40, 4), "{style}");
+        }
+    }
+
+    #[test]
+    fn no_masks_keep_frame_untouched() {
+        let original = frame(4, 4);
+        let masked = apply_masks(original.clone(), &[]);
+
+        assert!(masked.bytes.ptr_eq(&original.bytes));
+    }
+
+    #[test]
+    fn parses_styles() {
+        assert_eq!("fill".parse(), Ok(MaskStyle::Fill(Color::BLACK)));
+        assert_eq!(MaskStyle::Fill(Color::WHITE).to_string(), "#ffffff");
+        assert!("mosaic".parse::<MaskStyle>().is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! テスト用に、スクリプトどおりに前面ウィンドウが変わり、キャプチャが進む世界。
+
+use std::{
+    iter,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+use crate::{
+    window_capture::{
+        CaptureBackend, CaptureBackendFactory, CaptureSink, PixelFormat, TestPatternBackendFactory,
+    },
+    window_id::{Backend, WindowId},
+    window_list::WindowInfo,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TimelineAction {
+    Focus,
+    Close,
+    /// タイトルを変える
+    Title(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimelineEvent {
+    pub at: Duration,
+    pub action: TimelineAction,
+    pub id: WindowId,
+}
+
+/// `t=0 focus 1; t=200ms focus 2; t=300ms title 2 Private Browsing; t=500ms close 1` のような
+/// スクリプトで表される出来事の列
+#[derive(Debug)]
+pub struct Timeline {
+    events: Vec<TimelineEvent>,
+}
+
+impl Timeline {
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut events = vec![];
+        for entry in script.split([';', '\n']) {
+            let args: Vec<_> = entry.split_whitespace().collect();
+            if args.is_empty() {
+                continue;
+            }
+
+            let [time, action, id, ref rest @ ..] = args[..] else {
+                return Err(format!("malformed timeline entry: {}", entry.trim()));
+            };
+
+            let Some(time) = time.strip_prefix("t=") else {
+                return Err(format!(
+                    "timeline entry must start with t=: {}",
+                    entry.trim()
+                ));
+            };
+
+            let action = match (action, rest) {
+                ("focus", []) => TimelineAction::Focus,
+                ("close", []) => TimelineAction::Close,
+                ("title", rest) => TimelineAction::Title(rest.join(" ")),
+                ("focus" | "close", _) => {
+                    return Err(format!("malformed timeline entry: {}", entry.trim()))
+                }
+                _ => return Err(format!("unknown timeline action {action}")),
+            };
+
+            let Ok(id) = id.parse() else {
+                return Err(format!("invalid window number {id}"));
+            };
+
+            events.push(TimelineEvent {
+                at: parse_duration(time)?,
+                action,
+                id: mock_window(id),
+            });
+        }
+
+        events.sort_by_key(|event| event.at);
+
+        Ok(Self { events })
+    }
+
+    pub fn events(&self) -> &[TimelineEvent] {
+        &self.events
+    }
+
+    /// id のタイトルの変化を時刻順に
+    fn titles(&self, id: WindowId) -> Vec<(Duration, String)> {
+        self.events
+            .iter()
+            .filter(|event| event.id == id)
+            .filter_map(|event| match &event.action {
+                TimelineAction::Title(title) => Some((event.at, title.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn close_time(&self, id: WindowId) -> Option<Duration> {
+        self.events
+            .iter()
+            .find(|event| event.action == TimelineAction::Close && event.id == id)
+            .map(|event| event.at)
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(pos) => s.split_at(pos),
+        None => (s, "ms"),
+    };
+
+    let Ok(value) = value.parse::<f64>() else {
+        return Err(format!("invalid time {s}"));
+    };
+
+    match unit {
+        "ms" => Ok(Duration::from_secs_f64(value / 1000.0)),
+        "s" => Ok(Duration::from_secs_f64(value)),
+        _ => Err(format!("unknown time unit {unit}")),
+    }
+}
+
+pub fn mock_window(n: u64) -> WindowId {
+    WindowId::new(Backend::Mock, n)
+}
+
+/// モックのウィンドウ n は、プロセス `mockN` の `Mock window N` というタイトルのウィンドウ。
+///
+/// ただし 10 以上のウィンドウは、ウィンドウ n / 10 が持つダイアログで同じプロセスに属する。
+/// たとえばウィンドウ 11 と 12 はウィンドウ 1 のダイアログで、プロセス `mock1` のもの。
+pub fn mock_window_info(id: WindowId) -> WindowInfo {
+    let n = id.raw();
+    let owner = (n >= 10).then_some(n / 10);
+    let process = owner.unwrap_or(n);
+    WindowInfo {
+        id,
+        title: format!("Mock window {n}"),
+        class: "MockWindow".into(),
+        pid: Some(process as u32),
+        process: Some(format!("mock{process}")),
+        owner: owner.map(mock_window),
+    }
+}
+
+/// ハーネスが時計を進める間隔。モックのキャプチャはそのたびに 1 フレーム送る
+pub const STEP: Duration = Duration::from_millis(10);
+
+// キャプチャのスレッドに知らせる時刻。処理し終えたら Sender を捨てて知らせる
+type Tick = (Instant, Sender<()>);
+
+/// スクリプトを、ハーネスが進める仮想の時計に合わせて再生する。
+///
+/// 前面ウィンドウの変化はハーネスが取り出してドライバーに渡す。キャプチャは tick のたびに
+/// 1 フレームずつ進み、ハーネスはそれを待ってからドライバーを進めるので、結果が実時間や
+/// スケジューラーに左右されない。
+pub struct MockScenario {
+    shared: Arc<Shared>,
+    // 次に取り出す出来事
+    next_event: usize,
+}
+
+struct Shared {
+    timeline: Timeline,
+    epoch: Instant,
+    captures: Mutex<Vec<Sender<Tick>>>,
+}
+
+impl MockScenario {
+    /// スクリプトの時刻は epoch から数える
+    pub fn new(script: &str, epoch: Instant) -> Result<Self, String> {
+        Ok(Self {
+            shared: Arc::new(Shared {
+                timeline: Timeline::parse(script)?,
+                epoch,
+                captures: Mutex::new(vec![]),
+            }),
+            next_event: 0,
+        })
+    }
+
+    /// 起点から at までに前面に来たウィンドウのうち、まだ取り出していないもの
+    pub fn take_focus_changes(&mut self, at: Duration) -> Vec<WindowInfo> {
+        let events = self.shared.timeline.events();
+        let mut focused = vec![];
+        while let Some(event) = events.get(self.next_event).filter(|event| event.at <= at) {
+            if event.action == TimelineAction::Focus {
+                focused.push(mock_window_info(event.id));
+            }
+            self.next_event += 1;
+        }
+        focused
+    }
+
+    /// ウィンドウ番号で塗りつぶした小さなフレームを送り、タイトルや閉じる時刻はスクリプトに従う
+    pub fn capture_backend(&self) -> CaptureBackendFactory {
+        let shared = Arc::clone(&self.shared);
+        Box::new(move |id| {
+            let timeline = &shared.timeline;
+            let mut backend = shared.backend(2, 2);
+            backend.titles = iter::once((shared.epoch, mock_window_info(id).title))
+                .chain(
+                    timeline
+                        .titles(id)
+                        .into_iter()
+                        .map(|(at, title)| (shared.epoch + at, title)),
+                )
+                .collect();
+            backend.close_at = timeline.close_time(id).map(|at| shared.epoch + at);
+            Box::new(backend)
+        })
+    }
+
+    /// テストパターンの代わりに、指定された大きさのフレームを同じように送る
+    pub fn test_pattern_backend(&self) -> TestPatternBackendFactory {
+        let shared = Arc::clone(&self.shared);
+        Box::new(move |config| Box::new(shared.backend(config.width, config.height)))
+    }
+
+    /// 動いているキャプチャをすべて now まで進め、どれも処理し終えるまで待つ
+    pub fn tick(&self, now: Instant) {
+        let (tx_done, rx_done) = unbounded();
+        self.shared
+            .captures
+            .lock()
+            .unwrap()
+            .retain(|tx_tick| tx_tick.send((now, tx_done.clone())).is_ok());
+        drop(tx_done);
+
+        // 何も届かず、すべての Sender が捨てられたら戻る
+        let _ = rx_done.recv();
+    }
+
+    /// 残っているキャプチャのスレッドを終わらせる
+    pub fn finish(&self) {
+        self.shared.captures.lock().unwrap().clear();
+    }
+}
+
+impl Shared {
+    fn backend(&self, width: u32, height: u32) -> MockCaptureBackend {
+        let (tx_tick, rx_tick) = unbounded();
+        self.captures.lock().unwrap().push(tx_tick);
+        MockCaptureBackend {
+            rx_tick,
+            done: None,
+            width,
+            height,
+            titles: vec![],
+            close_at: None,
+        }
+    }
+}
+
+/// tick のたびに、ウィンドウ番号で塗りつぶしたフレームを 1 枚送る
+struct MockCaptureBackend {
+    rx_tick: Receiver<Tick>,
+    // スレッドが終わりきるまで持っておき、ハーネスがチャンネルの切断まで待てるようにする
+    done: Option<Sender<()>>,
+    width: u32,
+    height: u32,
+    // タイトルが変わる時刻。最初のものは始めから付いているタイトル
+    titles: Vec<(Instant, String)>,
+    close_at: Option<Instant>,
+}
+
+impl CaptureBackend for MockCaptureBackend {
+    fn start(&mut self, mut sink: CaptureSink) -> Result<(), String> {
+        let id = sink.id();
+        let (width, height) = (self.width, self.height);
+
+        while let Ok((now, done)) = self.rx_tick.recv() {
+            if sink.is_stop_requested() {
+                self.done = Some(done);
+                return Ok(());
+            }
+
+            if self.close_at.is_some_and(|at| at <= now) {
+                sink.on_closed();
+                self.done = Some(done);
+                return Ok(());
+            }
+
+            if let Some((_, title)) = self.titles.iter().rev().find(|(at, _)| *at <= now) {
+                sink.set_title(title.clone());
+            }
+
+            let len = (width * height * 4) as usize;
+            sink.on_frame_arrived(
+                width,
+                height,
+                width as usize * 4,
+                PixelFormat::Rgba8,
+                vec![id.raw() as u8; len],
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timeline_script() {
+        let timeline = Timeline::parse("t=0 focus 1; t=200ms focus 2\nt=1.5s close 1").unwrap();
+        assert_eq!(
+            timeline.events(),
+            &[
+                TimelineEvent {
+                    at: Duration::ZERO,
+                    action: TimelineAction::Focus,
+                    id: mock_window(1),
+                },
+                TimelineEvent {
+                    at: Duration::from_millis(200),
+                    action: TimelineAction::Focus,
+                    id: mock_window(2),
+                },
+                TimelineEvent {
+                    at: Duration::from_millis(1500),
+                    action: TimelineAction::Close,
+                    id: mock_window(1),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_title_changes() {
+        let timeline = Timeline::parse("t=0 focus 1; t=100ms title 1 Inbox - personal").unwrap();
+        assert_eq!(
+            timeline.titles(mock_window(1)),
+            [(Duration::from_millis(100), "Inbox - personal".to_owned())]
+        );
+        assert!(timeline.titles(mock_window(2)).is_empty());
+    }
+
+    #[test]
+    fn sorts_events_by_time() {
+        let timeline = Timeline::parse("t=300 close 1; t=100 focus 1").unwrap();
+        let times: Vec<_> = timeline.events().iter().map(|e| e.at).collect();
+        assert_eq!(
+            times,
+            [Duration::from_millis(100), Duration::from_millis(300)]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        assert!(Timeline::parse("focus 1").is_err());
+        assert!(Timeline::parse("t=0 blink 1").is_err());
+        assert!(Timeline::parse("t=10m focus 1").is_err());
+        assert!(Timeline::parse("t=0 focus x").is_err());
+        assert!(Timeline::parse("t=0 focus 1 extra").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! 表示してよいウィンドウにフォーカスがないあいだ、代わりに出す画面。
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    draw::{self, Color},
+    window_capture::{CapturedFrame, PixelFormat},
+    window_id::{Backend, WindowId},
+};
+
+// 最後のフレームがないときの大きさ
+const DEFAULT_SIZE: (u32, u32) = (1280, 720);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceholderPolicy {
+    /// 最後のフレームを出したままにする
+    #[default]
+    Hold,
+    Slate,
+    /// 最後のフレームをぼかして出す
+    Blur,
+    /// 背景色だけを出す
+    Background,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaceholderConfig {
+    pub policy: PlaceholderPolicy,
+    // hold のとき、これだけ経ったらスレートに切り替える
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hold_timeout_secs: Option<f64>,
+    pub slate_text: String,
+    pub background: Color,
+}
+
+/// シェルからの設定の変更
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlaceholderChange {
+    Policy(PlaceholderPolicy),
+    HoldTimeout(Option<f64>),
+    SlateText(String),
+    Background(Color),
+}
+
+impl Default for PlaceholderConfig {
+    fn default() -> Self {
+        Self {
+            policy: PlaceholderPolicy::Hold,
+            hold_timeout_secs: None,
+            slate_text: "BE RIGHT BACK".into(),
+            // ビューアーの背景と同じクロマキー用の緑
+            background: Color([0, 255, 0, 255]),
+        }
+    }
+}
+
+impl PlaceholderConfig {
+    // 時刻に足してもあふれないよう、設定ファイルに大きな値が書かれていてもここまでにする
+    pub const MAX_HOLD_TIMEOUT_SECS: u64 = 3600;
+
+    pub fn hold_timeout(&self) -> Option<Duration> {
+        self.hold_timeout_secs
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .map(|timeout| timeout.min(Duration::from_secs(Self::MAX_HOLD_TIMEOUT_SECS)))
+    }
+
+    pub fn apply(&mut self, change: PlaceholderChange) {
+        match change {
+            PlaceholderChange::Policy(policy) => self.policy = policy,
+            PlaceholderChange::HoldTimeout(secs) => self.hold_timeout_secs = secs,
+            PlaceholderChange::SlateText(text) => self.slate_text = text,
+            PlaceholderChange::Background(color) => self.background = color,
+        }
+    }
+
+    /// policy で決まる代わりの画面を作る。hold なら何も作らない。
+    pub fn render(
+        &self,
+        policy: PlaceholderPolicy,
+        last_frame: Option<&CapturedFrame>,
+    ) -> Option<CapturedFrame> {
+        let (width, height) = last_frame.map_or(DEFAULT_SIZE, |frame| (frame.width, frame.height));
+        match (policy, last_frame) {
+            (PlaceholderPolicy::Hold, _) => None,
+            (PlaceholderPolicy::Blur, Some(frame)) => Some(blurred(frame)),
+            // ぼかすものがなければスレートにする
+            (PlaceholderPolicy::Slate | PlaceholderPolicy::Blur, _) => {
+                Some(slate(width, height, &self.slate_text, self.background))
+            }
+            (PlaceholderPolicy::Background, _) => Some(solid(width, height, self.background)),
+        }
+    }
+}
+
+impl fmt::Display for PlaceholderPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PlaceholderPolicy::Hold => "hold",
+            PlaceholderPolicy::Slate => "slate",
+            PlaceholderPolicy::Blur => "blur",
+            PlaceholderPolicy::Background => "background",
+        })
+    }
+}
+
+impl FromStr for PlaceholderPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            PlaceholderPolicy::Hold,
+            PlaceholderPolicy::Slate,
+            PlaceholderPolicy::Blur,
+            PlaceholderPolicy::Background,
+        ]
+        .into_iter()
+        .find(|policy| policy.to_string() == s)
+        .ok_or_else(|| format!("unknown placeholder {s}, expected hold, slate, blur or background"))
+    }
+}
+
+impl fmt::Display for PlaceholderConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.policy)?;
+        if let (PlaceholderPolicy::Hold, Some(secs)) = (self.policy, self.hold_timeout_secs) {
+            write!(f, " (slate after {secs}s)")?;
+        }
+        write!(
+            f,
+            ", slate {:?}, background {}",
+            self.slate_text, self.background
+        )
+    }
+}
+
+/// ブラックアウト中に出す真っ黒な画面
+pub fn blackout_frame(last_frame: Option<&CapturedFrame>) -> CapturedFrame {
+    let (width, height) = last_frame.map_or(DEFAULT_SIZE, |frame| (frame.width, frame.height));
+    solid(width, height, Color::BLACK)
+}
+
+pub fn placeholder_id() -> WindowId {
+    WindowId::new(Backend::Placeholder, 0)
+}
+
+fn frame(width: u32, height: u32, bytes: Vec<u8>) -> CapturedFrame {
+    CapturedFrame {
+        id: placeholder_id(),
+        width,
+        height,
+        stride: width as usize * 4,
+        format: PixelFormat::Rgba8,
+        timestamp: Instant::now(),
+        bytes: bytes.into(),
+        client_area: None,
+    }
+}
+
+fn solid(width: u32, height: u32, color: Color) -> CapturedFrame {
+    frame(width, height, color.0.repeat((width * height) as usize))
+}
+
+/// 背景色の真ん中に文字を出す
+fn slate(width: u32, height: u32, text: &str, background: Color) -> CapturedFrame {
+    let mut bytes = background.0.repeat((width * height) as usize);
+
+    // 幅の 8 割と高さの 1/8 に収まる範囲で大きく
+    let scale = (width * 4 / 5 / draw::text_width(text, 1).max(1))
+        .min(height / 8 / draw::LINE_HEIGHT)
+        .max(1);
+    let x = width.saturating_sub(draw::text_width(text, scale)) / 2;
+    let y = height.saturating_sub(draw::LINE_HEIGHT * scale) / 2;
+    draw::draw_text(
+        &mut bytes,
+        width,
+        height,
+        (x, y),
+        scale,
+        text,
+        background.contrasting().0,
+    );
+
+    frame(width, height, bytes)
+}
+
+/// 中身が読めない程度に強くぼかす
+fn blurred(last_frame: &CapturedFrame) -> CapturedFrame {
+    let (width, height) = (last_frame.width, last_frame.height);
+    let mut bytes = vec![0; (width * height) as usize * 4];
+    last_frame.write_rgba8(&mut bytes);
+    draw::blur(&mut bytes, width, height, (width.max(height) / 64).max(1));
+
+    frame(width, height, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_window;
+
+    fn last_frame() -> CapturedFrame {
+        // 左半分が白、右半分が黒
+        let bytes = (0..64 * 64)
+            .flat_map(|i| {
+                if i % 64 < 32 {
+                    [255; 4]
+                } else {
+                    [0, 0, 0, 255]
+                }
+            })
+            .collect::<Vec<_>>();
+        CapturedFrame {
+            id: mock_window(1),
+            ..frame(64, 64, bytes)
+        }
+    }
+
+    #[test]
+    fn caps_huge_hold_timeouts() {
+        let config = PlaceholderConfig {
+            hold_timeout_secs: Some(1e19),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.hold_timeout(),
+            Some(Duration::from_secs(
+                PlaceholderConfig::MAX_HOLD_TIMEOUT_SECS
+            ))
+        );
+    }
+
+    #[test]
+    fn hold_renders_nothing() {
+        let config = PlaceholderConfig::default();
+        assert!(config
+            .render(PlaceholderPolicy::Hold, Some(&last_frame()))
+            .is_none());
+    }
+
+    #[test]
+    fn slate_has_text_on_background() {
+        let config = PlaceholderConfig {
+            background: Color::BLACK,
+            ..Default::default()
+        };
+        let slate = config.render(PlaceholderPolicy::Slate, None).unwrap();
+
+        assert_eq!(slate.id, placeholder_id());
+        assert_eq!((slate.width, slate.height), DEFAULT_SIZE);
+        assert_eq!(slate.pixel(0, 0), Color::BLACK.0);
+        assert!((0..slate.width).any(|x| slate.pixel(x, slate.height / 2) == Color::WHITE.0));
+    }
+
+    #[test]
+    fn blur_softens_edges_of_last_frame() {
+        let config = PlaceholderConfig::default();
+        let blurred = config
+            .render(PlaceholderPolicy::Blur, Some(&last_frame()))
+            .unwrap();
+
+        assert_eq!((blurred.width, blurred.height), (64, 64));
+        let [r, ..] = blurred.pixel(32, 10);
+        assert!(r > 0 && r < 255);
+        assert_eq!(blurred.pixel(0, 10), [255; 4]);
+    }
+
+    #[test]
+    fn background_fills_with_color() {
+        let config = PlaceholderConfig::default();
+        let frame = config
+            .render(PlaceholderPolicy::Background, Some(&last_frame()))
+            .unwrap();
+
+        assert!(frame
+            .bytes
+            .chunks_exact(4)
+            .all(|px| px == config.background.0));
+    }
+}
@@ -0,0 +1,194 @@
+//! タイトルに出てしまう見せたくない状態 (プライベートブラウズなど) のあいだ、ウィンドウを隠す。
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::TitlePattern,
+    draw::{self, Color},
+    window_capture::{CapturedFrame, PixelFormat},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactStyle {
+    /// 真っ黒にする
+    #[default]
+    Blank,
+    Blur,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactConfig {
+    // どれかがタイトルに一致しているあいだ隠す
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub titles: Vec<TitlePattern>,
+    pub style: RedactStyle,
+}
+
+/// シェルからの設定の変更。番号は 1 始まり
+#[derive(Clone, Debug)]
+pub enum RedactChange {
+    Add(TitlePattern),
+    Remove(usize),
+    Style(RedactStyle),
+}
+
+impl RedactConfig {
+    pub fn apply(&mut self, change: RedactChange) -> Result<(), String> {
+        match change {
+            RedactChange::Add(pattern) => self.titles.push(pattern),
+            RedactChange::Remove(index) => {
+                if index == 0 || index > self.titles.len() {
+                    return Err(format!("no such redact pattern #{index}"));
+                }
+                self.titles.remove(index - 1);
+            }
+            RedactChange::Style(style) => self.style = style,
+        }
+
+        Ok(())
+    }
+
+    /// title を隠すことになったパターン
+    pub fn matching(&self, title: &str) -> Option<&TitlePattern> {
+        self.titles.iter().find(|pattern| pattern.is_match(title))
+    }
+
+    /// 元のフレームと同じ大きさで、中身の分からないフレームにする
+    pub fn render(&self, frame: &CapturedFrame) -> CapturedFrame {
+        let (width, height) = (frame.width, frame.height);
+        let bytes = match self.style {
+            RedactStyle::Blank => Color::BLACK.0.repeat((width * height) as usize),
+            RedactStyle::Blur => {
+                let mut bytes = vec![0; (width * height) as usize * 4];
+                frame.write_rgba8(&mut bytes);
+                draw::blur(&mut bytes, width, height, (width.max(height) / 32).max(8));
+                bytes
+            }
+        };
+
+        CapturedFrame {
+            stride: width as usize * 4,
+            format: PixelFormat::Rgba8,
+            bytes: bytes.into(),
+            ..frame.clone()
+        }
+    }
+}
+
+impl fmt::Display for RedactStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RedactStyle::Blank => "blank",
+            RedactStyle::Blur => "blur",
+        })
+    }
+}
+
+impl FromStr for RedactStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blank" => Ok(RedactStyle::Blank),
+            "blur" => Ok(RedactStyle::Blur),
+            _ => Err(format!("unknown redact style {s}, expected blank or blur")),
+        }
+    }
+}
+
+impl fmt::Display for RedactConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.titles.is_empty() {
+            return f.write_str("off");
+        }
+
+        write!(f, "{} when title matches", self.style)?;
+        for (i, pattern) in self.titles.iter().enumerate() {
+            write!(f, " #{} {pattern}", i + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::mock::mock_window;
+
+    fn config(patterns: &[&str], style: RedactStyle) -> RedactConfig {
+        RedactConfig {
+            titles: patterns
+                .iter()
+                .map(|pattern| TitlePattern::new(pattern).unwrap())
+                .collect(),
+            style,
+        }
+    }
+
+    #[test]
+    fn matches_any_pattern() {
+        let config = config(&["Private Browsing", "^1Password"], RedactStyle::Blank);
+
+        assert_eq!(
+            config
+                .matching("GitHub - Private Browsing")
+                .map(ToString::to_string),
+            Some("/Private Browsing/".into())
+        );
+        assert!(config.matching("1Password 8").is_some());
+        assert!(config.matching("Open 1Password").is_none());
+    }
+
+    #[test]
+    fn renders_blank_frame_of_same_size() {
+        let frame = CapturedFrame {
+            id: mock_window(1),
+            width: 3,
+            height: 2,
+            stride: 16,
+            format: PixelFormat::Bgrx8,
+            timestamp: Instant::now(),
+            bytes: vec![200; 32].into(),
+            client_area: None,
+        };
+
+        let redacted = config(&[], RedactStyle::Blank).render(&frame);
+        assert_eq!(
+            (redacted.id, redacted.width, redacted.height),
+            (frame.id, 3, 2)
+        );
+        assert!(redacted
+            .bytes
+            .chunks(4)
+            .all(|pixel| pixel == [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn adds_and_removes_patterns() {
+        let mut config = RedactConfig::default();
+        assert_eq!(config.to_string(), "off");
+
+        config
+            .apply(RedactChange::Add(
+                TitlePattern::new("Inbox - personal").unwrap(),
+            ))
+            .unwrap();
+        config
+            .apply(RedactChange::Style(RedactStyle::Blur))
+            .unwrap();
+        assert_eq!(
+            config.to_string(),
+            "blur when title matches #1 /Inbox - personal/"
+        );
+
+        assert!(config.apply(RedactChange::Remove(2)).is_err());
+        config.apply(RedactChange::Remove(1)).unwrap();
+        assert!(config.titles.is_empty());
+    }
+}
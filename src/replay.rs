@@ -0,0 +1,283 @@
+//! 直前の出力を覚えておき、あとからファイルに書き出せるようにする。
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    canvas::{CanvasConfig, CanvasSize, LetterboxFill, OutputCanvas},
+    draw::Color,
+    window_capture::CapturedFrame,
+};
+
+// 書き出すときのフレームレート。間のフレームは直前のものを繰り返す
+const FPS: u32 = 30;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+    // 0 なら何も覚えない
+    pub secs: f64,
+    // 覚えておくフレームの合計の上限。超えたら古いものから捨てる
+    pub max_mb: u64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            secs: 0.0,
+            max_mb: 512,
+        }
+    }
+}
+
+impl ReplayConfig {
+    // 時刻に足してもあふれないよう、設定ファイルに大きな値が書かれていてもここまでにする
+    pub const MAX_SECS: u64 = 3600;
+
+    pub fn length(&self) -> Duration {
+        Duration::try_from_secs_f64(self.secs)
+            .unwrap_or_default()
+            .min(Duration::from_secs(Self::MAX_SECS))
+    }
+
+    fn max_bytes(&self) -> usize {
+        (self.max_mb as usize).saturating_mul(1024 * 1024)
+    }
+}
+
+impl fmt::Display for ReplayConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.length().is_zero() {
+            return f.write_str("off");
+        }
+
+        write!(f, "last {}s (up to {} MB)", self.secs, self.max_mb)
+    }
+}
+
+/// 出力したフレームとその時刻の列。時刻は呼び出し側が渡す。
+#[derive(Default)]
+pub struct ReplayBuffer {
+    frames: VecDeque<(Instant, CapturedFrame)>,
+    bytes: usize,
+}
+
+/// 書き出すために取り出したフレームの列
+pub struct ReplayClip {
+    frames: Vec<(Instant, CapturedFrame)>,
+}
+
+impl ReplayBuffer {
+    pub fn record(&mut self, frame: CapturedFrame, config: &ReplayConfig, now: Instant) {
+        let length = config.length();
+        if length.is_zero() {
+            self.clear();
+            return;
+        }
+
+        self.bytes += frame.bytes.len();
+        self.frames.push_back((now, frame));
+
+        // 最新のフレームだけは残す
+        while self.frames.len() > 1
+            && (self.bytes > config.max_bytes() || self.frames[0].0 + length < now)
+        {
+            let (_, frame) = self.frames.pop_front().unwrap();
+            self.bytes -= frame.bytes.len();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
+
+    pub fn duration(&self) -> Duration {
+        match (self.frames.front(), self.frames.back()) {
+            (Some((first, _)), Some((last, _))) => *last - *first,
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// バイト列はコピーせずに共有するので、書き出しを別のスレッドに任せられる
+    pub fn clip(&self) -> ReplayClip {
+        ReplayClip {
+            frames: self.frames.iter().cloned().collect(),
+        }
+    }
+}
+
+impl ReplayClip {
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Y4M (YUV 4:4:4) で書き出し、書いたフレーム数を返す
+    pub fn save_y4m(&self, path: &Path) -> io::Result<usize> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let count = self.write_y4m(&mut writer)?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// 大きさは最後のフレームにそろえる。ほかの大きさのフレームは縮めて黒で余白を埋める
+    fn write_y4m(&self, writer: &mut impl Write) -> io::Result<usize> {
+        let Some((last_time, last_frame)) = self.frames.last() else {
+            return Ok(0);
+        };
+        let (width, height) = (last_frame.width, last_frame.height);
+        let mut canvas = OutputCanvas::default();
+        let config = CanvasConfig {
+            size: Some(CanvasSize { width, height }),
+            fill: LetterboxFill::Color(Color::BLACK),
+            ..Default::default()
+        };
+
+        writeln!(writer, "YUV4MPEG2 W{width} H{height} F{FPS}:1 Ip A1:1 C444")?;
+
+        let first_time = self.frames[0].0;
+        // 間隔を足していくと誤差が溜まるので、毎回先頭からの時刻を計算する
+        let at = |n: u64| first_time + Duration::from_nanos(n * 1_000_000_000 / u64::from(FPS));
+        let count =
+            (*last_time - first_time).as_nanos() as u64 * u64::from(FPS) / 1_000_000_000 + 1;
+        let mut planes = vec![0; width as usize * height as usize * 3];
+        let mut next = 0;
+        for n in 0..count {
+            let time = at(n);
+            // この時刻に出ていたフレームまで進める
+            let mut current = None;
+            while next < self.frames.len() && self.frames[next].0 <= time {
+                current = Some(&self.frames[next].1);
+                next += 1;
+            }
+            if let Some(frame) = current {
+                let frame = canvas.fit(frame.clone(), &config);
+                to_yuv444(&frame, &mut planes);
+            }
+
+            writer.write_all(b"FRAME\n")?;
+            writer.write_all(&planes)?;
+        }
+
+        Ok(count as usize)
+    }
+}
+
+/// BT.601 の限定範囲で、Y・U・V の順に平面ごとに並べる
+fn to_yuv444(frame: &CapturedFrame, planes: &mut [u8]) {
+    let len = (frame.width * frame.height) as usize;
+    let (y_plane, uv) = planes.split_at_mut(len);
+    let (u_plane, v_plane) = uv.split_at_mut(len);
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let [r, g, b, _] = frame.pixel(x, y).map(i32::from);
+            let i = (y * frame.width + x) as usize;
+            y_plane[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            u_plane[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v_plane[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::mock_window, window_capture::PixelFormat};
+
+    fn frame(width: u32, height: u32, rgba: [u8; 4]) -> CapturedFrame {
+        CapturedFrame {
+            id: mock_window(1),
+            width,
+            height,
+            stride: width as usize * 4,
+            format: PixelFormat::Rgba8,
+            timestamp: Instant::now(),
+            bytes: rgba.repeat((width * height) as usize).into(),
+            client_area: None,
+        }
+    }
+
+    fn config(secs: f64, max_mb: u64) -> ReplayConfig {
+        ReplayConfig { secs, max_mb }
+    }
+
+    #[test]
+    fn caps_huge_lengths() {
+        let config = config(1e19, 1);
+        assert_eq!(config.length(), Duration::from_secs(ReplayConfig::MAX_SECS));
+
+        let mut buffer = ReplayBuffer::default();
+        buffer.record(frame(2, 2, [0; 4]), &config, Instant::now());
+        buffer.record(frame(2, 2, [0; 4]), &config, Instant::now());
+        assert_eq!(buffer.frames.len(), 2);
+    }
+
+    #[test]
+    fn keeps_only_recent_frames() {
+        let config = config(1.0, 1);
+        let now = Instant::now();
+        let mut buffer = ReplayBuffer::default();
+        for i in 0..30 {
+            buffer.record(
+                frame(2, 2, [0; 4]),
+                &config,
+                now + Duration::from_millis(i * 100),
+            );
+        }
+
+        assert_eq!(buffer.duration(), Duration::from_secs(1));
+
+        // 上限を超えたら古いものから捨てる
+        buffer.record(
+            frame(512, 512, [0; 4]),
+            &config,
+            now + Duration::from_secs(3),
+        );
+        buffer.record(
+            frame(512, 512, [0; 4]),
+            &config,
+            now + Duration::from_secs(3),
+        );
+        assert_eq!(buffer.clip().frames.len(), 1);
+
+        buffer.record(frame(2, 2, [0; 4]), &ReplayConfig::default(), now);
+        assert!(buffer.clip().is_empty());
+    }
+
+    #[test]
+    fn writes_y4m_at_constant_rate() {
+        let config = config(10.0, 16);
+        let now = Instant::now();
+        let mut buffer = ReplayBuffer::default();
+        buffer.record(frame(2, 2, [255, 255, 255, 255]), &config, now);
+        buffer.record(
+            frame(2, 2, [0, 0, 0, 255]),
+            &config,
+            now + Duration::from_millis(100),
+        );
+
+        let mut out = vec![];
+        let count = buffer.clip().write_y4m(&mut out).unwrap();
+
+        // 0ms, 33ms, 66ms, 100ms の 4 枚
+        let header = b"YUV4MPEG2 W2 H2 F30:1 Ip A1:1 C444\n";
+        let frame_len = b"FRAME\n".len() + 2 * 2 * 3;
+        assert_eq!(count, 4);
+        assert!(out.starts_with(header));
+        assert_eq!(out.len(), header.len() + frame_len * 4);
+
+        // 白は Y が 235、黒は 16 になる
+        let y_of = |n: usize| out[header.len() + frame_len * n + b"FRAME\n".len()];
+        assert_eq!(y_of(0), 235);
+        assert_eq!(y_of(3), 16);
+    }
+}
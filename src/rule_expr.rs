@@ -0,0 +1,629 @@
+//! ウィンドウの情報で許可・拒否を決める条件式。
+//!
+//! ```text
+//! title ~ /Figma/ && !(title ~ /Password/i)
+//! process == "code" || class in ["Alacritty", "kitty"]
+//! ```
+//!
+//! 使える項目は `title`・`class`・`process` で、演算子は `~` `!~` (正規表現)、`==` `!=`、`in [...]`、
+//! それらを `!` `&&` `||` と括弧で組み合わせる。
+
+use std::{fmt, str::FromStr};
+
+use regex::{Regex, RegexBuilder};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::window_list::WindowInfo;
+
+#[derive(Clone, Debug)]
+pub struct RuleExpr {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Match(Field, Regex),
+    Equals(Field, String),
+    In(Field, Vec<String>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Title,
+    Class,
+    Process,
+}
+
+/// 式のどこがおかしいのか。`column` は 1 始まりの文字単位。
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl RuleExpr {
+    pub fn eval(&self, info: &WindowInfo) -> bool {
+        self.expr.eval(info)
+    }
+}
+
+impl Expr {
+    fn eval(&self, info: &WindowInfo) -> bool {
+        match self {
+            Expr::Not(expr) => !expr.eval(info),
+            Expr::And(lhs, rhs) => lhs.eval(info) && rhs.eval(info),
+            Expr::Or(lhs, rhs) => lhs.eval(info) || rhs.eval(info),
+            Expr::Match(field, re) => re.is_match(field.value(info)),
+            Expr::Equals(field, value) => field.equals(info, value),
+            Expr::In(field, values) => values.iter().any(|value| field.equals(info, value)),
+        }
+    }
+}
+
+impl Field {
+    fn value(self, info: &WindowInfo) -> &str {
+        match self {
+            Field::Title => &info.title,
+            Field::Class => &info.class,
+            // プロセスがわからないときは空文字列として扱う
+            Field::Process => info.process.as_deref().unwrap_or_default(),
+        }
+    }
+
+    fn equals(self, info: &WindowInfo, value: &str) -> bool {
+        match self {
+            Field::Process => same_process(self.value(info), value),
+            _ => self.value(info) == value,
+        }
+    }
+}
+
+/// Windows では大文字小文字も `.exe` の有無も気にしない
+pub fn same_process(a: &str, b: &str) -> bool {
+    fn normalize(name: &str) -> String {
+        let name = name.to_lowercase();
+        match name.strip_suffix(".exe") {
+            Some(stem) => stem.to_owned(),
+            None => name,
+        }
+    }
+
+    normalize(a) == normalize(b)
+}
+
+impl ParseError {
+    fn at(source: &str, offset: usize, message: impl Into<String>) -> Self {
+        Self {
+            column: source[..offset].chars().count() + 1,
+            message: message.into(),
+        }
+    }
+
+    /// 式の下に `^` で問題の位置を示した 2 行
+    pub fn show(&self, source: &str) -> String {
+        format!(
+            "{source}\n{:width$}^ {}",
+            "",
+            self.message,
+            width = self.column - 1
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl fmt::Display for RuleExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for RuleExpr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            source: s,
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+
+        let expr = parser.parse_or()?;
+        let (token, offset) = parser.peek();
+        if *token != Token::End {
+            return Err(ParseError::at(
+                s,
+                offset,
+                format!("unexpected {token}, expected && or ||"),
+            ));
+        }
+
+        Ok(Self {
+            source: s.trim().to_owned(),
+            expr,
+        })
+    }
+}
+
+impl Serialize for RuleExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for RuleExpr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e: ParseError| de::Error::custom(format!("{e}\n{}", e.show(&s))))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Regex(String, bool),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Not,
+    And,
+    Or,
+    Tilde,
+    NotTilde,
+    Eq,
+    Ne,
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{name}`"),
+            Token::Str(s) => write!(f, "string {s:?}"),
+            Token::Regex(re, _) => write!(f, "regex /{re}/"),
+            Token::LParen => f.write_str("`(`"),
+            Token::RParen => f.write_str("`)`"),
+            Token::LBracket => f.write_str("`[`"),
+            Token::RBracket => f.write_str("`]`"),
+            Token::Comma => f.write_str("`,`"),
+            Token::Not => f.write_str("`!`"),
+            Token::And => f.write_str("`&&`"),
+            Token::Or => f.write_str("`||`"),
+            Token::Tilde => f.write_str("`~`"),
+            Token::NotTilde => f.write_str("`!~`"),
+            Token::Eq => f.write_str("`==`"),
+            Token::Ne => f.write_str("`!=`"),
+            Token::End => f.write_str("end of rule"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let mut next_is = |expected: char| chars.next_if(|&(_, c)| c == expected).is_some();
+
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ',' => Token::Comma,
+            '~' => Token::Tilde,
+            '!' if next_is('~') => Token::NotTilde,
+            '!' if next_is('=') => Token::Ne,
+            '!' => Token::Not,
+            '=' if next_is('=') => Token::Eq,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '"' | '/' => {
+                // `/` の中では `\/` だけを `/` にして、ほかのエスケープは正規表現にそのまま渡す
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, end)) if end == c => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) if escaped == c || c == '"' => text.push(escaped),
+                            Some((_, escaped)) => {
+                                text.push('\\');
+                                text.push(escaped);
+                            }
+                            None => {}
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => {
+                            let what = if c == '"' { "string" } else { "regex" };
+                            return Err(ParseError::at(
+                                source,
+                                offset,
+                                format!("unterminated {what}"),
+                            ));
+                        }
+                    }
+                }
+
+                if c == '"' {
+                    Token::Str(text)
+                } else {
+                    let ignore_case = chars.next_if(|&(_, c)| c == 'i').is_some();
+                    Token::Regex(text, ignore_case)
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut name = String::from(c);
+                while let Some((_, c)) = chars.next_if(|&(_, c)| c.is_alphanumeric() || c == '_') {
+                    name.push(c);
+                }
+                Token::Ident(name)
+            }
+            c => {
+                return Err(ParseError::at(
+                    source,
+                    offset,
+                    format!("unexpected character `{c}`"),
+                ))
+            }
+        };
+
+        tokens.push((token, offset));
+    }
+
+    tokens.push((Token::End, source.len()));
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    // 今いる式の木の深さ。評価や破棄も再帰するので、深すぎる式はスタックを溢れさせる
+    depth: usize,
+}
+
+impl Parser<'_> {
+    const MAX_DEPTH: usize = 128;
+
+    fn peek(&self) -> (&Token, usize) {
+        let (token, offset) = &self.tokens[self.pos];
+        (token, *offset)
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let (token, offset) = self.tokens[self.pos].clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        (token, offset)
+    }
+
+    fn eat(&mut self, expected: &Token) -> bool {
+        if self.peek().0 == expected {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, offset: usize, message: impl Into<String>) -> ParseError {
+        ParseError::at(self.source, offset, message)
+    }
+
+    /// 今の位置のトークンで木が一段深くなる
+    fn descend(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > Self::MAX_DEPTH {
+            let offset = self.peek().1;
+            return Err(self.error(
+                offset,
+                format!("rule is nested more than {} levels deep", Self::MAX_DEPTH),
+            ));
+        }
+        Ok(())
+    }
+
+    // a || b || c は左に伸びる木になるので、演算子が続くたびに一段深くなる
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let depth = self.depth;
+        let mut lhs = self.parse_and()?;
+        while self.peek().0 == &Token::Or {
+            self.descend()?;
+            self.pos += 1;
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let depth = self.depth;
+        let mut lhs = self.parse_unary()?;
+        while self.peek().0 == &Token::And {
+            self.descend()?;
+            self.pos += 1;
+            lhs = Expr::And(Box::new(lhs), Box::new(self.parse_unary()?));
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let depth = self.depth;
+        let expr = match self.peek().0 {
+            Token::Not => {
+                self.descend()?;
+                self.pos += 1;
+                Expr::Not(Box::new(self.parse_unary()?))
+            }
+            Token::LParen => {
+                self.descend()?;
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                let (token, offset) = self.next();
+                if token != Token::RParen {
+                    return Err(self.error(offset, format!("unexpected {token}, expected `)`")));
+                }
+                expr
+            }
+            _ => self.parse_comparison()?,
+        };
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let (token, offset) = self.next();
+        let field = match token {
+            Token::Ident(name) => match name.as_str() {
+                "title" => Field::Title,
+                "class" => Field::Class,
+                "process" => Field::Process,
+                _ => {
+                    return Err(self.error(
+                        offset,
+                        format!("unknown field `{name}`, expected title, class or process"),
+                    ))
+                }
+            },
+            token => return Err(self.error(offset, format!("unexpected {token}, expected field"))),
+        };
+
+        let (op, offset) = self.next();
+        match op {
+            Token::Tilde | Token::NotTilde => {
+                let re = self.parse_regex()?;
+                let expr = Expr::Match(field, re);
+                Ok(if op == Token::NotTilde {
+                    Expr::Not(Box::new(expr))
+                } else {
+                    expr
+                })
+            }
+            Token::Eq | Token::Ne => {
+                let expr = Expr::Equals(field, self.parse_string()?);
+                Ok(if op == Token::Ne {
+                    Expr::Not(Box::new(expr))
+                } else {
+                    expr
+                })
+            }
+            Token::Ident(name) if name == "in" => Ok(Expr::In(field, self.parse_list()?)),
+            token => Err(self.error(
+                offset,
+                format!("unexpected {token}, expected ~, !~, ==, != or in"),
+            )),
+        }
+    }
+
+    fn parse_regex(&mut self) -> Result<Regex, ParseError> {
+        match self.next() {
+            (Token::Regex(pattern, ignore_case), offset) => RegexBuilder::new(&pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| {
+                    // regex のエラーは複数行になるので最後の説明だけ使う
+                    let e = e.to_string();
+                    let reason = e.lines().last().unwrap_or_default().trim().to_owned();
+                    self.error(offset, format!("invalid regex: {reason}"))
+                }),
+            (token, offset) => {
+                Err(self.error(offset, format!("unexpected {token}, expected /regex/")))
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, ParseError> {
+        match self.next() {
+            (Token::Str(s), _) => Ok(s),
+            (token, offset) => {
+                Err(self.error(offset, format!("unexpected {token}, expected string")))
+            }
+        }
+    }
+
+    fn parse_list(&mut self) -> Result<Vec<String>, ParseError> {
+        let (token, offset) = self.next();
+        if token != Token::LBracket {
+            return Err(self.error(offset, format!("unexpected {token}, expected `[`")));
+        }
+
+        let mut values = vec![];
+        if self.eat(&Token::RBracket) {
+            return Ok(values);
+        }
+
+        loop {
+            values.push(self.parse_string()?);
+            match self.next() {
+                (Token::Comma, _) => {
+                    // 末尾のカンマは許す
+                    if self.eat(&Token::RBracket) {
+                        return Ok(values);
+                    }
+                }
+                (Token::RBracket, _) => return Ok(values),
+                (token, offset) => {
+                    return Err(self.error(offset, format!("unexpected {token}, expected , or ]")))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window_id::{Backend, WindowId};
+
+    fn window(title: &str, class: &str, process: Option<&str>) -> WindowInfo {
+        WindowInfo {
+            id: WindowId::new(Backend::Mock, 1),
+            title: title.into(),
+            class: class.into(),
+            pid: Some(1),
+            process: process.map(Into::into),
+            owner: None,
+        }
+    }
+
+    fn eval(expr: &str, info: &WindowInfo) -> bool {
+        expr.parse::<RuleExpr>().unwrap().eval(info)
+    }
+
+    #[test]
+    fn evaluates_comparisons() {
+        let figma = window("Design - Figma", "Chrome_WidgetWin_1", Some("Figma.exe"));
+
+        assert!(eval("title ~ /Figma/", &figma));
+        assert!(eval("title ~ /figma/i", &figma));
+        assert!(!eval("title ~ /figma/", &figma));
+        assert!(eval("title !~ /Password/", &figma));
+        assert!(eval("process == \"figma\"", &figma));
+        assert!(eval("class != \"Code\"", &figma));
+        assert!(eval("class in [\"Code\", \"Chrome_WidgetWin_1\",]", &figma));
+        assert!(!eval("class in []", &figma));
+    }
+
+    #[test]
+    fn combines_with_precedence() {
+        let login = window("Password - Figma", "Chrome", None);
+        let design = window("Design - Figma", "Chrome", None);
+
+        let expr = "title ~ /Figma/ && !(title ~ /Password/)";
+        assert!(!eval(expr, &login));
+        assert!(eval(expr, &design));
+
+        // && は || より強く結びつく
+        let expr = "class == \"Code\" || title ~ /Design/ && class == \"Chrome\"";
+        assert!(eval(expr, &design));
+        assert!(!eval(expr, &login));
+        assert!(eval("!!(title ~ /Design/)", &design));
+    }
+
+    #[test]
+    fn missing_process_is_empty() {
+        let info = window("a", "b", None);
+        assert!(eval("process == \"\"", &info));
+        assert!(eval("process != \"code\"", &info));
+    }
+
+    #[test]
+    fn unescapes_literals() {
+        let info = window("a/b \"c\"", "", None);
+        assert!(eval(r#"title ~ /a\/b/"#, &info));
+        assert!(eval(r#"title ~ /\s"c"$/"#, &info));
+        assert!(eval(r#"title == "a/b \"c\"""#, &info));
+    }
+
+    #[test]
+    fn reports_error_columns() {
+        let cases = [
+            ("title ~ Figma", 9, "unexpected `Figma`, expected /regex/"),
+            (
+                "name == \"x\"",
+                1,
+                "unknown field `name`, expected title, class or process",
+            ),
+            (
+                "title ~ /a/ &&",
+                15,
+                "unexpected end of rule, expected field",
+            ),
+            ("(title ~ /a/", 13, "unexpected end of rule, expected `)`"),
+            (
+                "title ~ /a/ title",
+                13,
+                "unexpected `title`, expected && or ||",
+            ),
+            ("title == \"abc", 10, "unterminated string"),
+            (
+                "class in [\"a\" \"b\"]",
+                15,
+                "unexpected string \"b\", expected , or ]",
+            ),
+            ("title ~ /a/ & x", 13, "unexpected character `&`"),
+        ];
+
+        for (source, column, message) in cases {
+            let err = source.parse::<RuleExpr>().unwrap_err();
+            assert_eq!(
+                (err.column, err.message.as_str()),
+                (column, message),
+                "{source}"
+            );
+        }
+
+        let err = "title ~ /(/".parse::<RuleExpr>().unwrap_err();
+        assert_eq!(err.column, 9);
+        assert!(err.message.starts_with("invalid regex"));
+    }
+
+    #[test]
+    fn rejects_deeply_nested_rules() {
+        let nested = |depth: usize| {
+            format!(
+                "{}title ~ /a/{}",
+                "!(".repeat(depth / 2),
+                ")".repeat(depth / 2)
+            )
+        };
+        assert!(nested(Parser::MAX_DEPTH).parse::<RuleExpr>().is_ok());
+
+        let err = nested(100_000).parse::<RuleExpr>().unwrap_err();
+        assert_eq!(
+            err.message,
+            format!("rule is nested more than {} levels deep", Parser::MAX_DEPTH)
+        );
+
+        let chain = vec!["title ~ /a/"; 100_000].join(" || ");
+        assert!(chain.parse::<RuleExpr>().is_err());
+    }
+
+    #[test]
+    fn shows_caret_under_error() {
+        let source = "title ~ Figma";
+        let err = source.parse::<RuleExpr>().unwrap_err();
+        assert_eq!(
+            err.show(source),
+            "title ~ Figma\n        ^ unexpected `Figma`, expected /regex/"
+        );
+    }
+}
@@ -1,10 +1,23 @@
-use std::{ffi::OsString, fmt::Write as _, os::windows::prelude::OsStringExt, thread};
+use std::{fmt::Write as _, path::PathBuf, thread};
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossbeam_channel::{select, unbounded, Receiver, Sender};
 use rustyline::{DefaultEditor, ExternalPrinter};
-use windows::Win32::{
-    Foundation::{BOOL, HWND, LPARAM},
-    UI::WindowsAndMessaging::{EnumWindows, GetWindowLongW, GetWindowTextW, GWL_STYLE},
+
+use crate::{
+    canvas::CanvasChange,
+    config::{RuleKind, TitlePattern, WindowRule},
+    control::{parse_blackout, BlackoutChange},
+    crop::Crop,
+    delay_line::DelayConfig,
+    mask::{Mask, MaskStyle},
+    placeholder::{PlaceholderChange, PlaceholderConfig},
+    redact::RedactChange,
+    replay::ReplayConfig,
+    rule_expr::{ParseError, RuleExpr},
+    transition::TransitionKind,
+    window_capture::TestPatternConfig,
+    window_id::WindowId,
+    window_list::{self, WindowInfo},
 };
 
 pub struct StdinShell {
@@ -20,22 +33,89 @@ pub enum StdinShellCommand {
 
 pub enum StdinShellMessage {
     QuitRequested,
-    AllowHWND(Vec<HWND>),
+    Allow(Vec<WindowId>),
+    // そのウィンドウのプロセスのウィンドウをすべて許可する
+    AllowProcess(Vec<WindowInfo>),
+    // そのウィンドウと、それが持つダイアログなどを許可する
+    AllowOwner(Vec<WindowId>),
+    // 個別の許可を取り消す
+    Revoke(Vec<WindowInfo>),
+    // ルールなどで許可されていても出さない
+    Deny(Vec<WindowId>),
+    Clear,
+    Pin(WindowInfo),
+    Unpin,
+    Follow(bool),
+    AddRule(RuleKind, WindowRule),
+    RemoveRule(RuleKind, usize),
+    // expr が None なら設定されているルールで試す
+    TestRules {
+        expr: Option<RuleExpr>,
+        windows: Vec<ScanEntry>,
+    },
+    // None なら今の設定を表示する
+    Placeholder(Option<PlaceholderChange>),
+    // 前面に来てから切り替えるまでの時間。None なら今の設定を表示する
+    Dwell(Option<u64>),
+    // 切り替え方と、省略できるその時間。None なら今の設定を表示する
+    Transition(Option<(TransitionKind, Option<u64>)>),
+    // 空なら今の設定を表示する
+    Canvas(Vec<CanvasChange>),
+    // None なら切り抜きをやめる
+    Crop(WindowInfo, Option<Crop>),
+    // None なら今の設定を表示する
+    Redact(Option<RedactChange>),
+    Blackout(BlackoutChange),
+    // 出力を遅らせる秒数。None なら今の設定を表示する
+    Delay(Option<f64>),
+    // 出力を覚えておく秒数。None なら今の設定を表示する
+    Replay(Option<f64>),
+    SaveReplay(PathBuf),
+    AddMask(WindowInfo, Mask),
+    RemoveMask(WindowInfo, String),
+    // None ならキャプチャしているすべてのウィンドウ
+    ListMasks(Option<WindowInfo>),
     ListRequested,
+    ShowTestPattern(TestPatternConfig),
 }
 
-struct ScanEntry {
-    alias: Option<char>,
-    hwnd: HWND,
-    title: String,
+#[derive(Clone)]
+pub struct ScanEntry {
+    pub alias: Option<char>,
+    pub info: WindowInfo,
 }
 
 enum UserInput {
     Nop,
     Quit,
-    AllowHWND(Vec<HWND>),
+    Allow(Vec<WindowId>),
+    AllowProcess(Vec<WindowInfo>),
+    AllowOwner(Vec<WindowId>),
+    Revoke(Vec<WindowInfo>),
+    Deny(Vec<WindowId>),
+    Clear,
+    Pin(WindowInfo),
+    Unpin,
+    Follow(bool),
+    AddRule(RuleKind, WindowRule),
+    RemoveRule(RuleKind, usize),
+    TestRules(Option<RuleExpr>),
+    Placeholder(Option<PlaceholderChange>),
+    Dwell(Option<u64>),
+    Transition(Option<(TransitionKind, Option<u64>)>),
+    Canvas(Vec<CanvasChange>),
+    Crop(WindowInfo, Option<Crop>),
+    Redact(Option<RedactChange>),
+    Blackout(BlackoutChange),
+    Delay(Option<f64>),
+    Replay(Option<f64>),
+    SaveReplay(PathBuf),
+    AddMask(WindowInfo, Mask),
+    RemoveMask(WindowInfo, String),
+    ListMasks(Option<WindowInfo>),
     List,
     Scan,
+    TestPattern(TestPatternConfig),
 }
 
 impl StdinShell {
@@ -64,42 +144,145 @@ impl StdinShell {
         thread::spawn(move || keep_asking(editor, tx_input));
 
         loop {
-            if let Ok(line) = rx_input.try_recv() {
-                match self.parse_command(&line) {
-                    Ok(UserInput::Nop) => {}
-                    Ok(UserInput::Quit) => {
-                        let _ = self.tx_msg.send(StdinShellMessage::QuitRequested);
+            select! {
+                recv(rx_input) -> line => {
+                    let Ok(line) = line else {
+                        break;
+                    };
+
+                    if !self.handle_input(&line, &mut printer) {
                         break;
                     }
-                    Ok(UserInput::AllowHWND(hwnds)) => {
-                        let _ = self.tx_msg.send(StdinShellMessage::AllowHWND(hwnds));
-                    }
-                    Ok(UserInput::List) => {
-                        let _ = self.tx_msg.send(StdinShellMessage::ListRequested);
-                        printer
-                            .print("Requesting allowed HWNDs, press Enter to refresh...".into())
-                            .unwrap();
-                    }
-                    Ok(UserInput::Scan) => {
-                        self.scan(&mut printer);
-                    }
-                    Err(e) => printer.print(format!("shell: {e}")).unwrap(),
                 }
-            };
-
-            if let Ok(cmd) = self.rx_cmd.try_recv() {
-                match cmd {
-                    StdinShellCommand::Quit => break,
-                    StdinShellCommand::Output { message } => {
+                recv(self.rx_cmd) -> cmd => match cmd {
+                    Ok(StdinShellCommand::Quit) | Err(_) => break,
+                    Ok(StdinShellCommand::Output { message }) => {
                         printer.print(message).unwrap();
                     }
+                },
+            }
+        }
+    }
+
+    /// 入力された 1 行を処理する。シェルを終了するときは false を返す。
+    fn handle_input<E: ExternalPrinter>(&mut self, line: &str, printer: &mut E) -> bool {
+        match self.parse_command(line) {
+            Ok(UserInput::Nop) => {}
+            Ok(UserInput::Quit) => {
+                let _ = self.tx_msg.send(StdinShellMessage::QuitRequested);
+                return false;
+            }
+            Ok(UserInput::Allow(ids)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Allow(ids));
+            }
+            Ok(UserInput::AllowProcess(windows)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::AllowProcess(windows));
+            }
+            Ok(UserInput::AllowOwner(ids)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::AllowOwner(ids));
+            }
+            Ok(UserInput::Revoke(windows)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Revoke(windows));
+            }
+            Ok(UserInput::Deny(ids)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Deny(ids));
+            }
+            Ok(UserInput::Clear) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Clear);
+            }
+            Ok(UserInput::Pin(info)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Pin(info));
+            }
+            Ok(UserInput::Unpin) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Unpin);
+            }
+            Ok(UserInput::Follow(follows)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Follow(follows));
+            }
+            Ok(UserInput::AddRule(kind, rule)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::AddRule(kind, rule));
+            }
+            Ok(UserInput::RemoveRule(kind, index)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::RemoveRule(kind, index));
+            }
+            Ok(UserInput::TestRules(expr)) => {
+                if self.scan_result.is_empty() {
+                    printer
+                        .print("shell: no windows to test; run scan first".into())
+                        .unwrap();
+                } else {
+                    let _ = self.tx_msg.send(StdinShellMessage::TestRules {
+                        expr,
+                        windows: self.scan_result.clone(),
+                    });
                 }
             }
+            Ok(UserInput::Placeholder(change)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Placeholder(change));
+            }
+            Ok(UserInput::Dwell(dwell_ms)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Dwell(dwell_ms));
+            }
+            Ok(UserInput::Transition(change)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Transition(change));
+            }
+            Ok(UserInput::Canvas(changes)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Canvas(changes));
+            }
+            Ok(UserInput::Crop(info, crop)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Crop(info, crop));
+            }
+            Ok(UserInput::Redact(change)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Redact(change));
+            }
+            Ok(UserInput::Blackout(change)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Blackout(change));
+            }
+            Ok(UserInput::Delay(delay_secs)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Delay(delay_secs));
+            }
+            Ok(UserInput::Replay(replay_secs)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::Replay(replay_secs));
+            }
+            Ok(UserInput::SaveReplay(path)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::SaveReplay(path));
+            }
+            Ok(UserInput::AddMask(info, mask)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::AddMask(info, mask));
+            }
+            Ok(UserInput::RemoveMask(info, name)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::RemoveMask(info, name));
+            }
+            Ok(UserInput::ListMasks(info)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::ListMasks(info));
+            }
+            Ok(UserInput::List) => {
+                let _ = self.tx_msg.send(StdinShellMessage::ListRequested);
+            }
+            Ok(UserInput::Scan) => {
+                self.scan(printer);
+            }
+            Ok(UserInput::TestPattern(config)) => {
+                let _ = self.tx_msg.send(StdinShellMessage::ShowTestPattern(config));
+            }
+            Err(e) => printer.print(format!("shell: {e}")).unwrap(),
         }
+
+        true
     }
 
     fn scan<E: ExternalPrinter>(&mut self, printer: &mut E) {
-        let mut scan_result = enumerate_windows();
+        let windows = match window_list::enumerate_windows() {
+            Ok(windows) => windows,
+            Err(e) => {
+                printer
+                    .print(format!("shell: failed to scan: {e}"))
+                    .unwrap();
+                return;
+            }
+        };
+
+        let mut scan_result: Vec<_> = windows.into_iter().map(ScanEntry::from).collect();
         for (alias, entry) in ('A'..='Z').zip(&mut scan_result) {
             entry.alias = Some(alias);
         }
@@ -110,10 +293,11 @@ impl StdinShell {
         for entry in &scan_result {
             writeln!(
                 &mut buf,
-                "| {}) [{:>8}] {}",
+                "| {}) [{:>12}] {} ({})",
                 entry.alias.unwrap_or(' '),
-                entry.hwnd.0,
-                entry.title
+                entry.info.id,
+                entry.info.title,
+                entry.info.class
             )
             .unwrap();
         }
@@ -142,34 +326,475 @@ impl StdinShell {
             return Ok(UserInput::Scan);
         }
 
-        if args[0].starts_with("allow") {
-            if args.len() == 1 {
-                return Err("allow needs at least one HWND".into());
-            }
-
-            let mut hwnds = vec![];
-            'next_arg: for arg in &args[1..] {
-                if arg.len() == 1 {
-                    for entry in &self.scan_result {
-                        if entry.alias == Some(arg.chars().next().unwrap()) {
-                            hwnds.push(entry.hwnd);
-                            continue 'next_arg;
-                        }
-                    }
+        if args[0] == "rule" || args[0] == "rules" {
+            return self.parse_rule_command(line, &args);
+        }
+
+        if args[0] == "placeholder" {
+            return parse_placeholder(&args[1..]).map(UserInput::Placeholder);
+        }
+
+        if args[0] == "dwell" {
+            return match args[1..] {
+                [] => Ok(UserInput::Dwell(None)),
+                [ms] => match ms.trim_end_matches("ms").parse() {
+                    Ok(ms) => Ok(UserInput::Dwell(Some(ms))),
+                    Err(_) => Err(format!("invalid dwell time {ms}")),
+                },
+                _ => Err("usage: dwell [<ms>]".into()),
+            };
+        }
+
+        if args[0] == "transition" {
+            return parse_transition(&args[1..]).map(UserInput::Transition);
+        }
+
+        if args[0] == "crop" {
+            let [_, _, crop] = args[..] else {
+                return Err("usage: crop <window> client|<x>,<y>,<width>,<height>|off".into());
+            };
+            let ids = self.parse_windows(&args[..2])?;
+            let crop = match crop {
+                "off" => None,
+                crop => Some(crop.parse()?),
+            };
+            return Ok(UserInput::Crop(self.window_info(ids[0]), crop));
+        }
+
+        if args[0] == "blackout" {
+            return parse_blackout(&args[1..]).map(UserInput::Blackout);
+        }
+
+        if args[0] == "delay" {
+            return match args[1..] {
+                [] => Ok(UserInput::Delay(None)),
+                [secs] => {
+                    parse_secs(secs, DelayConfig::MAX_SECS).map(|secs| UserInput::Delay(Some(secs)))
                 }
+                _ => Err("usage: delay [<secs>|off]".into()),
+            };
+        }
 
-                let Ok(hwnd) = arg.parse() else {
-                return Err(format!("unknown HWND {arg} in allow"));
+        if args[0] == "replay" {
+            return match args[1..] {
+                [] => Ok(UserInput::Replay(None)),
+                ["save", ..] => {
+                    // パスには空白も入りうるので、引用符を考慮して分け直す
+                    let args = split_args(line)?;
+                    let [_, _, path] = &args[..] else {
+                        return Err("usage: replay save <path>".into());
+                    };
+                    Ok(UserInput::SaveReplay(path.into()))
+                }
+                [secs] => parse_secs(secs, ReplayConfig::MAX_SECS)
+                    .map(|secs| UserInput::Replay(Some(secs))),
+                _ => Err("usage: replay [<secs>|off] | replay save <path>".into()),
             };
+        }
+
+        if args[0] == "redact" {
+            return parse_redact(&args[1..]).map(UserInput::Redact);
+        }
+
+        if args[0] == "mask" || args[0] == "masks" {
+            return self.parse_mask_command(&args);
+        }
+
+        if args[0] == "canvas" {
+            return parse_canvas(&args[1..]).map(UserInput::Canvas);
+        }
+
+        if args[0] == "pattern" {
+            return parse_test_pattern(&args[1..]).map(UserInput::TestPattern);
+        }
 
-                hwnds.push(HWND(hwnd));
+        if args[0] == "allow-process" {
+            let mut windows = vec![];
+            for id in self.parse_windows(&args)? {
+                // scan していないウィンドウも指定できるように、その場で調べる
+                let info = match self.scan_result.iter().find(|entry| entry.info.id == id) {
+                    Some(entry) => entry.info.clone(),
+                    None => window_list::window_info(id)?,
+                };
+                windows.push(info);
             }
 
-            return Ok(UserInput::AllowHWND(hwnds));
+            return Ok(UserInput::AllowProcess(windows));
+        }
+
+        if args[0] == "allow-owner" {
+            return self.parse_windows(&args).map(UserInput::AllowOwner);
+        }
+
+        if args[0].starts_with("allow") {
+            return self.parse_windows(&args).map(UserInput::Allow);
+        }
+
+        if args[0] == "revoke" {
+            let windows = self.parse_windows(&args)?;
+            return Ok(UserInput::Revoke(
+                windows.into_iter().map(|id| self.window_info(id)).collect(),
+            ));
+        }
+
+        if args[0] == "deny" {
+            return self.parse_windows(&args).map(UserInput::Deny);
+        }
+
+        if args[0] == "clear" {
+            return Ok(UserInput::Clear);
+        }
+
+        if args[0] == "pin" {
+            let [id] = self.parse_windows(&args)?[..] else {
+                return Err("pin takes only one window".into());
+            };
+            return Ok(UserInput::Pin(self.window_info(id)));
+        }
+
+        if args[0] == "unpin" {
+            return Ok(UserInput::Unpin);
+        }
+
+        if args[0] == "follow" {
+            return match args.get(1) {
+                Some(&"on") => Ok(UserInput::Follow(true)),
+                Some(&"off") => Ok(UserInput::Follow(false)),
+                _ => Err("usage: follow on|off".into()),
+            };
         }
 
         Err(format!("unknown command: {line}"))
     }
+
+    /// `<command> <alias|id>...` のウィンドウを取り出す
+    fn parse_windows(&self, args: &[&str]) -> Result<Vec<WindowId>, String> {
+        if args.len() == 1 {
+            return Err(format!("{} needs at least one window", args[0]));
+        }
+
+        let mut ids = vec![];
+        for arg in &args[1..] {
+            if let Some(entry) = self.scan_entry(arg) {
+                ids.push(entry.info.id);
+                continue;
+            }
+
+            let Ok(id) = arg.parse() else {
+                return Err(format!("unknown window {arg} in {}", args[0]));
+            };
+
+            ids.push(id);
+        }
+
+        Ok(ids)
+    }
+
+    /// ```text
+    /// rule add|deny [<alias>] [title=<regex>] [class=<name>] [process=<name>] [crop=<crop>] | when <expr>
+    /// rule remove [deny] <number>
+    /// rules test [<expr>]
+    /// ```
+    fn parse_rule_command(&self, line: &str, args: &[&str]) -> Result<UserInput, String> {
+        match args.get(1) {
+            Some(&"add") | Some(&"deny") => {
+                let kind = if args[1] == "add" {
+                    RuleKind::Allow
+                } else {
+                    RuleKind::Deny
+                };
+
+                // 式はそのまま残りすべて。rule も add も deny も when を含まないので最初に現れる when が区切り
+                if args.get(2) == Some(&"when") {
+                    let (_, source) = line.split_once("when").unwrap();
+                    let rule = WindowRule {
+                        when: Some(parse_expr(source.trim())?),
+                        ..Default::default()
+                    };
+                    return Ok(UserInput::AddRule(kind, rule));
+                }
+
+                // タイトルの正規表現には空白も入りうるので、引用符を考慮して分け直す
+                let args = split_args(line)?;
+                self.parse_rule(&args[2..])
+                    .map(|rule| UserInput::AddRule(kind, rule))
+            }
+            Some(&"remove") => {
+                let (kind, number) = match args.get(2) {
+                    Some(&"deny") => (RuleKind::Deny, args.get(3)),
+                    number => (RuleKind::Allow, number),
+                };
+                let Some(Ok(index)) = number.map(|arg| arg.trim_start_matches('#').parse()) else {
+                    return Err("rule remove needs a rule number".into());
+                };
+                Ok(UserInput::RemoveRule(kind, index))
+            }
+            Some(&"test") => {
+                let (_, source) = line.split_once("test").unwrap();
+                let source = source.trim();
+                if source.is_empty() {
+                    Ok(UserInput::TestRules(None))
+                } else {
+                    parse_expr(source).map(|expr| UserInput::TestRules(Some(expr)))
+                }
+            }
+            _ => Err(
+                "usage: rule add|deny <alias> | [title=<regex>] [class=<name>] \
+                      [process=<name>] [crop=<crop>] | when <expr>, rule remove [deny] <number>, \
+                      rules test [<expr>]"
+                    .into(),
+            ),
+        }
+    }
+
+    /// ```text
+    /// mask add <window> <name> <x>,<y>,<width>,<height> [fill|#rrggbb|pixelate|blur]
+    /// mask remove <window> <name>
+    /// masks [list [<window>]]
+    /// ```
+    fn parse_mask_command(&self, args: &[&str]) -> Result<UserInput, String> {
+        let window = |arg: &str| {
+            let ids = self.parse_windows(&[args[0], arg])?;
+            Ok::<_, String>(self.window_info(ids[0]))
+        };
+
+        match args[1..] {
+            ["add", id, name, region, ref style @ ..] if style.len() <= 1 => {
+                let style = match style.first() {
+                    Some(style) => style.parse()?,
+                    None => MaskStyle::default(),
+                };
+                let mask = Mask {
+                    name: name.into(),
+                    region: region.parse()?,
+                    style,
+                };
+                Ok(UserInput::AddMask(window(id)?, mask))
+            }
+            ["remove", id, name] => Ok(UserInput::RemoveMask(window(id)?, name.into())),
+            [] | ["list"] => Ok(UserInput::ListMasks(None)),
+            ["list", id] => Ok(UserInput::ListMasks(Some(window(id)?))),
+            _ => Err("usage: mask add <window> <name> <x>,<y>,<width>,<height> \
+                      [fill|#rrggbb|pixelate|blur], mask remove <window> <name>, \
+                      mask list [<window>]"
+                .into()),
+        }
+    }
+
+    /// 先頭が `<alias>` ならそのウィンドウのクラス名とプロセス名、あとは `key=value` の組から作る
+    fn parse_rule(&self, args: &[String]) -> Result<WindowRule, String> {
+        let mut rule = WindowRule::default();
+        for (i, arg) in args.iter().enumerate() {
+            match arg.split_once('=') {
+                Some(("title", pattern)) => rule.title = Some(TitlePattern::new(pattern)?),
+                Some(("class", class)) => rule.class = Some(class.into()),
+                Some(("process", process)) => rule.process = Some(process.into()),
+                // 切り抜きは条件ではなく、当てはまったウィンドウに使う
+                Some(("crop", crop)) => rule.crop = Some(crop.parse()?),
+                None if i == 0 && self.scan_entry(arg).is_some() => {
+                    let info = &self.scan_entry(arg).unwrap().info;
+                    rule.class = Some(info.class.clone()).filter(|class| !class.is_empty());
+                    rule.process = info.process.clone();
+                }
+                _ => return Err(format!("unknown condition {arg} in rule")),
+            }
+        }
+
+        if rule.is_empty() {
+            return Err("rule needs at least one of title, class, process or when".into());
+        }
+
+        Ok(rule)
+    }
+
+    /// scan したときの情報か、なければその場で調べたもの
+    fn window_info(&self, id: WindowId) -> WindowInfo {
+        match self.scan_result.iter().find(|entry| entry.info.id == id) {
+            Some(entry) => entry.info.clone(),
+            None => window_list::window_info(id).unwrap_or_else(|_| WindowInfo::unknown(id)),
+        }
+    }
+
+    fn scan_entry(&self, alias: &str) -> Option<&ScanEntry> {
+        let mut chars = alias.chars();
+        let (Some(alias), None) = (chars.next(), chars.next()) else {
+            return None;
+        };
+
+        self.scan_result
+            .iter()
+            .find(|entry| entry.alias == Some(alias))
+    }
+}
+
+fn parse_expr(source: &str) -> Result<RuleExpr, String> {
+    source
+        .parse()
+        .map_err(|e: ParseError| format!("invalid rule: {e}\n{}", e.show(source)))
+}
+
+/// 空白で区切る。`"` で囲んだ部分は空白を含めてひとつとして扱い、その中では `\"` で `"` を書ける。
+fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut current: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('"') => arg.push('"'),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err("unterminated quote".into()),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated quote".into()),
+                    }
+                }
+            }
+            c if c.is_whitespace() => args.extend(current.take()),
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+
+    Ok(args)
+}
+
+/// `placeholder [hold|slate|blur|background | timeout <secs>|off | text <text> | color #rrggbb]`
+fn parse_placeholder(args: &[&str]) -> Result<Option<PlaceholderChange>, String> {
+    let change = match args {
+        [] => return Ok(None),
+        ["timeout", "off"] => PlaceholderChange::HoldTimeout(None),
+        ["timeout", secs] => match secs.parse::<f64>() {
+            Ok(secs) if secs > PlaceholderConfig::MAX_HOLD_TIMEOUT_SECS as f64 => {
+                return Err(format!(
+                    "timeout must be at most {}s in placeholder",
+                    PlaceholderConfig::MAX_HOLD_TIMEOUT_SECS
+                ))
+            }
+            Ok(secs) if secs.is_finite() && secs >= 0.0 => {
+                PlaceholderChange::HoldTimeout(Some(secs))
+            }
+            _ => return Err(format!("invalid timeout {secs} in placeholder")),
+        },
+        ["text", text @ ..] if !text.is_empty() => PlaceholderChange::SlateText(text.join(" ")),
+        ["color", color] => PlaceholderChange::Background(color.parse()?),
+        [policy] => PlaceholderChange::Policy(policy.parse()?),
+        _ => {
+            return Err(
+                "usage: placeholder [hold|slate|blur|background | timeout <secs>|off | text <text> | color #rrggbb]"
+                    .into(),
+            )
+        }
+    };
+
+    Ok(Some(change))
+}
+
+/// `<secs>` か、0 秒と同じ `off`。`max` 秒までにする
+fn parse_secs(secs: &str, max: u64) -> Result<f64, String> {
+    if secs == "off" {
+        return Ok(0.0);
+    }
+
+    match secs.trim_end_matches('s').parse::<f64>() {
+        Ok(secs) if secs > max as f64 => Err(format!("time must be at most {max}s")),
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Ok(secs),
+        _ => Err(format!("invalid time {secs}")),
+    }
+}
+
+/// `redact [add <regex> | remove <number> | blank|blur]`
+fn parse_redact(args: &[&str]) -> Result<Option<RedactChange>, String> {
+    let change = match args {
+        [] => return Ok(None),
+        ["add", pattern @ ..] if !pattern.is_empty() => {
+            RedactChange::Add(TitlePattern::new(&pattern.join(" "))?)
+        }
+        ["remove", index] => match index.trim_start_matches('#').parse() {
+            Ok(index) => RedactChange::Remove(index),
+            Err(_) => return Err(format!("invalid redact pattern number {index}")),
+        },
+        [style] => RedactChange::Style(style.parse()?),
+        _ => return Err("usage: redact [add <regex> | remove <number> | blank|blur]".into()),
+    };
+
+    Ok(Some(change))
+}
+
+/// `transition [cut|crossfade|slide|zoom [<ms>]]`
+fn parse_transition(args: &[&str]) -> Result<Option<(TransitionKind, Option<u64>)>, String> {
+    match args {
+        [] => Ok(None),
+        [kind] => Ok(Some((kind.parse()?, None))),
+        [kind, ms] => match ms.trim_end_matches("ms").parse() {
+            Ok(ms) => Ok(Some((kind.parse()?, Some(ms)))),
+            Err(_) => Err(format!("invalid transition time {ms}")),
+        },
+        _ => Err("usage: transition [cut|crossfade|slide|zoom [<ms>]]".into()),
+    }
+}
+
+/// `canvas [off|<width>x<height>] [nearest|bilinear|lanczos] [blur|#rrggbb]`
+fn parse_canvas(args: &[&str]) -> Result<Vec<CanvasChange>, String> {
+    args.iter()
+        .map(|arg| {
+            if *arg == "off" {
+                Ok(CanvasChange::Size(None))
+            } else if arg.contains('x') {
+                arg.parse().map(|size| CanvasChange::Size(Some(size)))
+            } else if let Ok(filter) = arg.parse() {
+                Ok(CanvasChange::Filter(filter))
+            } else {
+                arg.parse()
+                    .map(CanvasChange::Fill)
+                    .map_err(|_| format!("unknown canvas setting {arg}"))
+            }
+        })
+        .collect()
+}
+
+/// `pattern [<width>x<height>] [<fps>]`
+fn parse_test_pattern(args: &[&str]) -> Result<TestPatternConfig, String> {
+    let mut config = TestPatternConfig::default();
+    for arg in args {
+        if let Some((width, height)) = arg.split_once('x') {
+            let (Ok(width), Ok(height)) = (width.parse(), height.parse()) else {
+                return Err(format!("invalid size {arg} in pattern"));
+            };
+            config.width = width;
+            config.height = height;
+        } else {
+            let Ok(fps) = arg.parse() else {
+                return Err(format!("invalid fps {arg} in pattern"));
+            };
+            config.fps = fps;
+        }
+    }
+
+    if config.width == 0 || config.height == 0 || config.fps == 0 {
+        return Err("pattern size and fps must be positive".into());
+    }
+
+    let max = TestPatternConfig::MAX_SIZE;
+    if config.width > max || config.height > max {
+        return Err(format!("pattern size must be at most {max}x{max}"));
+    }
+    if config.fps > TestPatternConfig::MAX_FPS {
+        return Err(format!(
+            "pattern fps must be at most {}",
+            TestPatternConfig::MAX_FPS
+        ));
+    }
+
+    Ok(config)
 }
 
 fn keep_asking(mut editor: DefaultEditor, sender: Sender<String>) {
@@ -183,41 +808,202 @@ fn keep_asking(mut editor: DefaultEditor, sender: Sender<String>) {
     }
 }
 
-fn enumerate_windows() -> Vec<ScanEntry> {
-    let mut windows: Vec<ScanEntry> = vec![];
-    unsafe {
-        EnumWindows(
-            Some(enumerate_callback),
-            LPARAM(&mut windows as *mut _ as isize),
-        )
-        .unwrap()
-    };
+impl From<WindowInfo> for ScanEntry {
+    fn from(info: WindowInfo) -> Self {
+        Self { alias: None, info }
+    }
+}
 
-    // 普通のウィンドウに限る
-    windows
-        .into_iter()
-        .filter(|win| {
-            let style = unsafe { GetWindowLongW(win.hwnd, GWL_STYLE) }; // GWL_STYLE
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{placeholder::PlaceholderPolicy, redact::RedactStyle};
+
+    #[test]
+    fn splits_quoted_args() {
+        assert_eq!(
+            split_args(r#"rule add title="Inbox - \"work\"" class=a\b"#).unwrap(),
+            ["rule", "add", r#"title=Inbox - "work""#, r"class=a\b"]
+        );
+        assert!(split_args(r#"title="Inbox"#).is_err());
+    }
 
-            // WS_VISIBLEとWS_CAPTION
-            (style & 0x10C00000) == 0x10C00000
-        })
-        .collect()
-}
+    #[test]
+    fn parses_rule_conditions() {
+        let (shell, _tx_cmd, _rx_msg) = StdinShell::new();
+
+        let Ok(UserInput::AddRule(RuleKind::Allow, rule)) =
+            shell.parse_command(r#"rule add title="^Figma - " process=figma"#)
+        else {
+            panic!("rule is not parsed");
+        };
+        assert_eq!(rule.to_string(), r#"title=/^Figma - / process="figma""#);
+
+        let Ok(UserInput::AddRule(_, rule)) =
+            shell.parse_command("rule add class=Code crop=0,5%,100%,90%")
+        else {
+            panic!("rule with crop is not parsed");
+        };
+        assert_eq!(rule.to_string(), r#"class="Code" crop=0,5%,100%,90%"#);
+        assert!(shell.parse_command("rule add crop=client").is_err());
+
+        assert!(shell.parse_command("rule add").is_err());
+        assert!(shell.parse_command("rule add name=figma").is_err());
+        assert!(shell.parse_command("rule add title=(").is_err());
+        assert!(matches!(
+            shell.parse_command("rule remove #2"),
+            Ok(UserInput::RemoveRule(RuleKind::Allow, 2))
+        ));
+        assert!(matches!(
+            shell.parse_command("rule remove deny 1"),
+            Ok(UserInput::RemoveRule(RuleKind::Deny, 1))
+        ));
+    }
+
+    #[test]
+    fn parses_rule_expressions() {
+        let (shell, _tx_cmd, _rx_msg) = StdinShell::new();
+
+        let Ok(UserInput::AddRule(RuleKind::Deny, rule)) =
+            shell.parse_command(r#"rule deny when title ~ /Password/ || class == "when""#)
+        else {
+            panic!("rule is not parsed");
+        };
+        assert_eq!(
+            rule.to_string(),
+            r#"when title ~ /Password/ || class == "when""#
+        );
+
+        assert!(matches!(
+            shell.parse_command("rules test"),
+            Ok(UserInput::TestRules(None))
+        ));
+        assert!(matches!(
+            shell.parse_command("rules test title ~ /a/"),
+            Ok(UserInput::TestRules(Some(_)))
+        ));
+
+        let Err(e) = shell.parse_command("rule add when title ~ /a/ && title") else {
+            panic!("invalid rule is parsed");
+        };
+        assert_eq!(
+            e,
+            "invalid rule: column 21: unexpected end of rule, expected ~, !~, ==, != or in\n\
+             title ~ /a/ && title\n                    ^ unexpected end of rule, expected ~, !~, ==, != or in"
+        );
+    }
 
-unsafe extern "system" fn enumerate_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
-    let windows = unsafe { &mut *(lparam.0 as *mut Vec<ScanEntry>) };
+    #[test]
+    fn parses_placeholder_settings() {
+        assert_eq!(parse_placeholder(&[]), Ok(None));
+        assert_eq!(
+            parse_placeholder(&["blur"]),
+            Ok(Some(PlaceholderChange::Policy(PlaceholderPolicy::Blur)))
+        );
+        assert_eq!(
+            parse_placeholder(&["timeout", "1.5"]),
+            Ok(Some(PlaceholderChange::HoldTimeout(Some(1.5))))
+        );
+        assert_eq!(
+            parse_placeholder(&["text", "back", "soon"]),
+            Ok(Some(PlaceholderChange::SlateText("back soon".into())))
+        );
+        assert!(parse_placeholder(&["timeout", "-1"]).is_err());
+        assert!(parse_placeholder(&["timeout", "1e19"]).is_err());
+        assert!(parse_placeholder(&["color", "green"]).is_err());
+        assert!(parse_placeholder(&["fade"]).is_err());
+    }
 
-    let mut title_u16 = vec![0; 1024];
-    GetWindowTextW(hwnd, &mut title_u16);
-    let title = OsString::from_wide(&title_u16)
-        .to_string_lossy()
-        .into_owned();
-    windows.push(ScanEntry {
-        alias: None,
-        hwnd,
-        title,
-    });
+    #[test]
+    fn parses_test_pattern_limits() {
+        assert_eq!(
+            parse_test_pattern(&["8192x4320", "240"]),
+            Ok(TestPatternConfig {
+                width: 8192,
+                height: 4320,
+                fps: 240,
+            })
+        );
+        assert!(parse_test_pattern(&["100000x100000"]).is_err());
+        assert!(parse_test_pattern(&["640x8193"]).is_err());
+        assert!(parse_test_pattern(&["1001"]).is_err());
+        assert!(parse_test_pattern(&["18446744073709551615"]).is_err());
+        assert!(parse_test_pattern(&["0"]).is_err());
+    }
+
+    #[test]
+    fn parses_mask_commands() {
+        let (shell, _tx_cmd, _rx_msg) = StdinShell::new();
+
+        let Ok(UserInput::AddMask(info, mask)) =
+            shell.parse_command("mask add mock:1 chat 0,80%,100%,20% pixelate")
+        else {
+            panic!("mask is not parsed");
+        };
+        assert_eq!(info.id.to_string(), "mock:1");
+        assert_eq!(mask.to_string(), "chat 0,80%,100%,20% pixelate");
 
-    BOOL(1)
+        let Ok(UserInput::AddMask(_, mask)) =
+            shell.parse_command("mask add mock:1 tabs 0,0,100%,40")
+        else {
+            panic!("mask without style is not parsed");
+        };
+        assert_eq!(mask.style, MaskStyle::default());
+
+        assert!(matches!(
+            shell.parse_command("mask remove mock:1 chat"),
+            Ok(UserInput::RemoveMask(_, name)) if name == "chat"
+        ));
+        assert!(matches!(
+            shell.parse_command("masks"),
+            Ok(UserInput::ListMasks(None))
+        ));
+        assert!(shell.parse_command("mask add mock:1 chat 0,0,10").is_err());
+        assert!(shell
+            .parse_command("mask add mock:1 chat 0,0,10,10 mosaic")
+            .is_err());
+    }
+
+    #[test]
+    fn parses_redact_commands() {
+        assert!(matches!(parse_redact(&[]), Ok(None)));
+        assert!(matches!(
+            parse_redact(&["add", "Private", "Browsing"]),
+            Ok(Some(RedactChange::Add(pattern))) if pattern.to_string() == "/Private Browsing/"
+        ));
+        assert!(matches!(
+            parse_redact(&["remove", "#2"]),
+            Ok(Some(RedactChange::Remove(2)))
+        ));
+        assert!(matches!(
+            parse_redact(&["blur"]),
+            Ok(Some(RedactChange::Style(RedactStyle::Blur)))
+        ));
+        assert!(parse_redact(&["add"]).is_err());
+        assert!(parse_redact(&["add", "("]).is_err());
+        assert!(parse_redact(&["mosaic"]).is_err());
+    }
+
+    #[test]
+    fn parses_replay_commands() {
+        let (shell, _tx_cmd, _rx_msg) = StdinShell::new();
+
+        assert!(matches!(
+            shell.parse_command("replay 30s"),
+            Ok(UserInput::Replay(Some(secs))) if secs == 30.0
+        ));
+        assert!(matches!(
+            shell.parse_command("replay off"),
+            Ok(UserInput::Replay(Some(secs))) if secs == 0.0
+        ));
+        assert!(matches!(
+            shell.parse_command(r#"replay save "/tmp/my clip.y4m""#),
+            Ok(UserInput::SaveReplay(path)) if path.as_os_str() == "/tmp/my clip.y4m"
+        ));
+        assert!(shell.parse_command("replay save").is_err());
+        assert!(shell.parse_command("replay -1").is_err());
+        assert!(shell.parse_command("replay 3600").is_ok());
+        assert!(shell.parse_command("replay 1e19").is_err());
+        assert!(shell.parse_command("delay 1e19").is_err());
+    }
 }
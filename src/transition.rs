@@ -0,0 +1,360 @@
+//! 出すウィンドウが変わったときに、前のフレームから次のフレームへ少しずつ切り替える。
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::window_capture::{CapturedFrame, PixelFormat};
+
+// 切り替え中に新しいフレームが届かなくても、この間隔で描き直す
+const TICK: Duration = Duration::from_millis(16);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransitionKind {
+    #[default]
+    Cut,
+    Crossfade,
+    /// 前のフレームを左へ押し出しながら、次のフレームが右から入ってくる
+    Slide,
+    /// 次のフレームが中央から大きくなりながら現れる
+    Zoom,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransitionConfig {
+    pub kind: TransitionKind,
+    pub duration_ms: u64,
+}
+
+impl Default for TransitionConfig {
+    fn default() -> Self {
+        Self {
+            kind: TransitionKind::Cut,
+            duration_ms: 300,
+        }
+    }
+}
+
+impl TransitionConfig {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+}
+
+/// ドライバーとビューアーのあいだに入り、フレームの送り元が変わったら切り替えの途中の絵を作る
+#[derive(Default)]
+pub struct TransitionStage {
+    // 最後に送り出したフレーム。切り替えが始まったらこれが前のフレームになる
+    last_output: Option<CapturedFrame>,
+    active: Option<ActiveTransition>,
+}
+
+struct ActiveTransition {
+    kind: TransitionKind,
+    from: CapturedFrame,
+    to: CapturedFrame,
+    started: Instant,
+    duration: Duration,
+    next_tick: Instant,
+}
+
+impl TransitionStage {
+    /// ビューアーに送るフレームを返す
+    pub fn process(
+        &mut self,
+        frame: CapturedFrame,
+        config: &TransitionConfig,
+        now: Instant,
+    ) -> CapturedFrame {
+        match (&mut self.active, &self.last_output) {
+            (Some(active), _) if active.to.id == frame.id => active.to = frame,
+            // 送り元が変わった。切り替えの途中でも、今見えているものから始め直す
+            (_, Some(last))
+                if last.id != frame.id
+                    && config.kind != TransitionKind::Cut
+                    && !config.duration().is_zero() =>
+            {
+                self.active = Some(ActiveTransition {
+                    kind: config.kind,
+                    from: last.clone(),
+                    to: frame,
+                    started: now,
+                    duration: config.duration(),
+                    next_tick: now,
+                });
+            }
+            _ => {
+                self.active = None;
+                self.last_output = Some(frame.clone());
+                return frame;
+            }
+        }
+
+        self.render(now)
+    }
+
+    /// 描き直す時刻
+    pub fn deadline(&self) -> Option<Instant> {
+        self.active.as_ref().map(|active| active.next_tick)
+    }
+
+    /// 描き直す時刻になっていれば、切り替えを進めたフレームを返す
+    pub fn tick(&mut self, now: Instant) -> Option<CapturedFrame> {
+        if self.deadline().is_some_and(|deadline| deadline <= now) {
+            Some(self.render(now))
+        } else {
+            None
+        }
+    }
+
+    fn render(&mut self, now: Instant) -> CapturedFrame {
+        let active = self.active.as_mut().unwrap();
+        let elapsed = now.saturating_duration_since(active.started);
+        let frame = if elapsed >= active.duration {
+            let frame = active.to.clone();
+            self.active = None;
+            frame
+        } else {
+            active.next_tick = now + TICK;
+            let progress = elapsed.as_secs_f32() / active.duration.as_secs_f32();
+            compose(active.kind, &active.from, &active.to, progress)
+        };
+
+        self.last_output = Some(frame.clone());
+        frame
+    }
+}
+
+/// 切り替えの途中の絵を作る。大きさの違うフレームは、大きさを補間したキャンバスに縦横比を保って収める
+pub fn compose(
+    kind: TransitionKind,
+    from: &CapturedFrame,
+    to: &CapturedFrame,
+    progress: f32,
+) -> CapturedFrame {
+    // 始めと終わりをゆるやかに
+    let t = progress.clamp(0.0, 1.0);
+    let t = t * t * (3.0 - 2.0 * t);
+
+    let lerp = |a: u32, b: u32| (a as f32 + (b as f32 - a as f32) * t).round().max(1.0) as u32;
+    let (width, height) = (lerp(from.width, to.width), lerp(from.height, to.height));
+    let mut canvas = Canvas {
+        width,
+        height,
+        bytes: [0, 0, 0, 255].repeat((width * height) as usize),
+    };
+
+    let (w, h) = (width as f32, height as f32);
+    match kind {
+        TransitionKind::Cut => canvas.draw(to, (0.0, 0.0, w, h), 1.0),
+        TransitionKind::Crossfade => {
+            canvas.draw(from, (0.0, 0.0, w, h), 1.0);
+            canvas.draw(to, (0.0, 0.0, w, h), t);
+        }
+        TransitionKind::Slide => {
+            canvas.draw(from, (-t * w, 0.0, w, h), 1.0);
+            canvas.draw(to, ((1.0 - t) * w, 0.0, w, h), 1.0);
+        }
+        TransitionKind::Zoom => {
+            let scale = 0.5 + 0.5 * t;
+            canvas.draw(from, (0.0, 0.0, w, h), 1.0);
+            canvas.draw(
+                to,
+                (
+                    w * (1.0 - scale) / 2.0,
+                    h * (1.0 - scale) / 2.0,
+                    w * scale,
+                    h * scale,
+                ),
+                t,
+            );
+        }
+    }
+
+    CapturedFrame {
+        id: to.id,
+        width,
+        height,
+        stride: width as usize * 4,
+        format: PixelFormat::Rgba8,
+        timestamp: to.timestamp,
+        bytes: canvas.bytes.into(),
+        client_area: None,
+    }
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    bytes: Vec<u8>,
+}
+
+impl Canvas {
+    /// 縦横比を保って rect に収まるように frame を描き、不透明度 alpha で重ねる
+    fn draw(&mut self, frame: &CapturedFrame, (x, y, w, h): (f32, f32, f32, f32), alpha: f32) {
+        let frame = frame.to_rgba8();
+        let scale = (w / frame.width as f32).min(h / frame.height as f32);
+        let (dw, dh) = (frame.width as f32 * scale, frame.height as f32 * scale);
+        let (dx, dy) = (x + (w - dw) / 2.0, y + (h - dh) / 2.0);
+        if scale <= 0.0 {
+            return;
+        }
+
+        let alpha = (alpha.clamp(0.0, 1.0) * 256.0) as u32;
+        let x_range = dx.max(0.0) as u32..((dx + dw).ceil().max(0.0) as u32).min(self.width);
+        let y_range = dy.max(0.0) as u32..((dy + dh).ceil().max(0.0) as u32).min(self.height);
+        for cy in y_range {
+            let sy = (((cy as f32 + 0.5 - dy) / scale) as u32).min(frame.height - 1);
+            let src_row = frame.row(sy);
+            for cx in x_range.clone() {
+                let sx = (((cx as f32 + 0.5 - dx) / scale) as u32).min(frame.width - 1);
+                let src = &src_row[sx as usize * 4..sx as usize * 4 + 3];
+                let i = (cy * self.width + cx) as usize * 4;
+                for (dst, &src) in self.bytes[i..i + 3].iter_mut().zip(src) {
+                    *dst = ((*dst as u32 * (256 - alpha) + src as u32 * alpha) >> 8) as u8;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for TransitionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransitionKind::Cut => "cut",
+            TransitionKind::Crossfade => "crossfade",
+            TransitionKind::Slide => "slide",
+            TransitionKind::Zoom => "zoom",
+        })
+    }
+}
+
+impl FromStr for TransitionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            TransitionKind::Cut,
+            TransitionKind::Crossfade,
+            TransitionKind::Slide,
+            TransitionKind::Zoom,
+        ]
+        .into_iter()
+        .find(|kind| kind.to_string() == s)
+        .ok_or_else(|| format!("unknown transition {s}, expected cut, crossfade, slide or zoom"))
+    }
+}
+
+impl fmt::Display for TransitionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            TransitionKind::Cut => write!(f, "cut"),
+            kind => write!(f, "{kind} {}ms", self.duration_ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_window;
+
+    fn frame(n: u64, width: u32, height: u32, value: u8) -> CapturedFrame {
+        CapturedFrame {
+            id: mock_window(n),
+            width,
+            height,
+            stride: width as usize * 4,
+            format: PixelFormat::Rgba8,
+            timestamp: Instant::now(),
+            bytes: vec![value; (width * height * 4) as usize].into(),
+            client_area: None,
+        }
+    }
+
+    #[test]
+    fn crossfade_blends_both_frames() {
+        let mixed = compose(
+            TransitionKind::Crossfade,
+            &frame(1, 4, 4, 0),
+            &frame(2, 4, 4, 200),
+            0.5,
+        );
+
+        assert_eq!(mixed.id, mock_window(2));
+        assert_eq!(mixed.pixel(2, 2), [100, 100, 100, 255]);
+    }
+
+    #[test]
+    fn slide_pushes_previous_frame_out() {
+        let slid = compose(
+            TransitionKind::Slide,
+            &frame(1, 8, 2, 10),
+            &frame(2, 8, 2, 200),
+            0.5,
+        );
+
+        assert_eq!(slid.pixel(0, 0)[0], 10);
+        assert_eq!(slid.pixel(7, 0)[0], 200);
+    }
+
+    #[test]
+    fn canvas_size_moves_between_frame_sizes() {
+        let from = frame(1, 100, 100, 10);
+        let to = frame(2, 200, 50, 200);
+
+        let start = compose(TransitionKind::Zoom, &from, &to, 0.0);
+        let middle = compose(TransitionKind::Zoom, &from, &to, 0.5);
+        let end = compose(TransitionKind::Zoom, &from, &to, 1.0);
+        assert_eq!((start.width, start.height), (100, 100));
+        assert_eq!((middle.width, middle.height), (150, 75));
+        assert_eq!((end.width, end.height), (200, 50));
+        assert_eq!(end.pixel(0, 0)[0], 200);
+    }
+
+    #[test]
+    fn stage_transitions_when_source_changes() {
+        let config = TransitionConfig {
+            kind: TransitionKind::Crossfade,
+            duration_ms: 100,
+        };
+        let t0 = Instant::now();
+        let ms = |ms| t0 + Duration::from_millis(ms);
+        let mut stage = TransitionStage::default();
+
+        let out = stage.process(frame(1, 2, 2, 0), &config, t0);
+        assert_eq!(out.pixel(0, 0)[0], 0);
+        assert_eq!(stage.deadline(), None);
+
+        let out = stage.process(frame(2, 2, 2, 200), &config, ms(10));
+        assert_eq!(out.pixel(0, 0)[0], 0);
+        assert_eq!(stage.deadline(), Some(ms(26)));
+
+        assert!(stage.tick(ms(20)).is_none());
+        let out = stage.tick(ms(60)).unwrap();
+        assert_eq!(out.pixel(0, 0)[0], 100);
+
+        let out = stage.tick(ms(110)).unwrap();
+        assert_eq!(out.pixel(0, 0)[0], 200);
+        assert_eq!(stage.deadline(), None);
+    }
+
+    #[test]
+    fn cut_passes_frames_through() {
+        let config = TransitionConfig::default();
+        let mut stage = TransitionStage::default();
+        let now = Instant::now();
+
+        stage.process(frame(1, 2, 2, 0), &config, now);
+        let second = frame(2, 2, 2, 200);
+        let out = stage.process(second.clone(), &config, now);
+        assert!(out.bytes.ptr_eq(&second.bytes));
+        assert_eq!(stage.deadline(), None);
+    }
+}
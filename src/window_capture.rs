@@ -1,40 +1,334 @@
-use crossbeam_channel::{unbounded, Receiver, Sender};
 use std::{
-    mem, slice,
+    thread,
     time::{Duration, Instant},
 };
-use windows::Win32::Foundation::HWND;
-use windows_capture::{
-    capture::{WindowsCaptureHandler, WindowsCaptureSettings},
-    frame::{Frame, RGBA},
-    window::Window,
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+use crate::{
+    frame_mailbox::FramePoster,
+    frame_pool::{FrameBuffer, FrameBufferMut, FramePool},
+    window_id::WindowId,
 };
 
+mod test_pattern;
+#[cfg(windows)]
+mod win32;
+#[cfg(unix)]
+mod x11;
+
+pub use test_pattern::{TestPatternBackend, TestPatternConfig};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8,
+    Bgra8,
+    /// アルファチャンネルの位置に意味のない値が入っている RGBA
+    Rgbx8,
+    /// アルファチャンネルの位置に意味のない値が入っている BGRA
+    Bgrx8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        4
+    }
+
+    pub fn to_rgba(self, pixel: &[u8]) -> [u8; 4] {
+        match self {
+            PixelFormat::Rgba8 => [pixel[0], pixel[1], pixel[2], pixel[3]],
+            PixelFormat::Bgra8 => [pixel[2], pixel[1], pixel[0], pixel[3]],
+            PixelFormat::Rgbx8 => [pixel[0], pixel[1], pixel[2], 255],
+            PixelFormat::Bgrx8 => [pixel[2], pixel[1], pixel[0], 255],
+        }
+    }
+}
+
+/// フレームの中の長方形。単位は画素
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone)]
 pub struct CapturedFrame {
-    pub hwnd: HWND,
+    pub id: WindowId,
     pub width: u32,
     pub height: u32,
-    pub bytes: Vec<u8>,
+    /// 1 行あたりのバイト数。行末に詰め物が入っていることがある。
+    pub stride: usize,
+    pub format: PixelFormat,
+    pub timestamp: Instant,
+    pub bytes: FrameBuffer,
+    /// タイトルバーや枠を除いたクライアント領域。分からなければ None
+    pub client_area: Option<Rect>,
 }
 
-pub struct WindowCapture {
-    _rx_cmd: Receiver<WindowCaptureCommand>,
+impl CapturedFrame {
+    /// 詰め物を含まない y 行目の画素
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.stride;
+        &self.bytes[start..start + self.width as usize * self.format.bytes_per_pixel()]
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let bpp = self.format.bytes_per_pixel();
+        let start = x as usize * bpp;
+        self.format.to_rgba(&self.row(y)[start..start + bpp])
+    }
+
+    pub fn is_packed_rgba8(&self) -> bool {
+        self.format == PixelFormat::Rgba8 && self.stride == self.width as usize * 4
+    }
+
+    /// 詰め物のない RGBA8 として dst に書き出す。dst は width * height * 4 バイト必要。
+    pub fn write_rgba8(&self, dst: &mut [u8]) {
+        let row_len = self.width as usize * 4;
+        for (y, dst_row) in dst.chunks_exact_mut(row_len).enumerate() {
+            let src_row = self.row(y as u32);
+            if self.format == PixelFormat::Rgba8 {
+                dst_row.copy_from_slice(src_row);
+                continue;
+            }
+
+            let bpp = self.format.bytes_per_pixel();
+            for (dst, src) in dst_row.chunks_exact_mut(4).zip(src_row.chunks_exact(bpp)) {
+                dst.copy_from_slice(&self.format.to_rgba(src));
+            }
+        }
+    }
+
+    /// 詰め物のない RGBA8 に変換する。すでにその形式ならコピーせずに共有する。
+    pub fn to_rgba8(&self) -> CapturedFrame {
+        if self.is_packed_rgba8() {
+            return self.clone();
+        }
+
+        let mut bytes = vec![0; self.width as usize * self.height as usize * 4];
+        self.write_rgba8(&mut bytes);
+
+        CapturedFrame {
+            stride: self.width as usize * 4,
+            format: PixelFormat::Rgba8,
+            bytes: bytes.into(),
+            ..self.clone()
+        }
+    }
+
+    /// rect の部分だけを切り出す。rect はフレームに収まっていること
+    pub fn crop(&self, rect: Rect) -> CapturedFrame {
+        if rect == self.bounds() {
+            return self.clone();
+        }
+
+        let bpp = self.format.bytes_per_pixel();
+        let (start, end) = (rect.x as usize * bpp, (rect.x + rect.width) as usize * bpp);
+        let mut bytes = Vec::with_capacity(rect.width as usize * rect.height as usize * bpp);
+        for y in rect.y..rect.y + rect.height {
+            bytes.extend_from_slice(&self.row(y)[start..end]);
+        }
+
+        CapturedFrame {
+            width: rect.width,
+            height: rect.height,
+            stride: rect.width as usize * bpp,
+            bytes: bytes.into(),
+            client_area: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// キャプチャの実装。プラットフォームごとに用意する。
+pub trait CaptureBackend: Send {
+    /// キャプチャを開始する。ウィンドウが閉じられるか停止が要求されるまで戻らない。
+    fn start(&mut self, sink: CaptureSink) -> Result<(), String>;
+}
+
+pub type CaptureBackendFactory = Box<dyn Fn(WindowId) -> Box<dyn CaptureBackend> + Send>;
+
+pub type TestPatternBackendFactory =
+    Box<dyn Fn(TestPatternConfig) -> Box<dyn CaptureBackend> + Send>;
+
+#[cfg(windows)]
+pub fn default_backend(_id: WindowId) -> Box<dyn CaptureBackend> {
+    Box::new(win32::Win32CaptureBackend::new(60))
+}
+
+#[cfg(unix)]
+pub fn default_backend(_id: WindowId) -> Box<dyn CaptureBackend> {
+    Box::new(x11::X11CaptureBackend::new(60))
+}
+
+/// 指定されたフレームレートを超えないよう、次にフレームを送ってよい時刻を管理する
+pub struct FramePacer {
+    fps: u64,
+    interval: Duration,
+    next_update: Instant,
+}
+
+impl FramePacer {
+    // 0 では割れず、速すぎると待たずに回り続けてしまう
+    pub const MAX_FPS: u64 = 240;
+
+    /// fps は 1 から MAX_FPS に収める
+    pub fn new(fps: u64) -> Self {
+        let fps = fps.clamp(1, Self::MAX_FPS);
+        Self {
+            fps,
+            interval: Duration::from_secs(1) / fps as u32,
+            next_update: Instant::now(),
+        }
+    }
+
+    pub fn fps(&self) -> u64 {
+        self.fps
+    }
+
+    pub fn is_due(&self) -> bool {
+        Instant::now() >= self.next_update
+    }
+
+    pub fn wait(&self) {
+        let now = Instant::now();
+        if now < self.next_update {
+            thread::sleep(self.next_update - now);
+        }
+    }
+
+    pub fn compute_next_update(&mut self) {
+        let mut next_update = self.next_update + self.interval;
+        let now = Instant::now();
+        if now < next_update {
+            next_update = now + self.interval;
+        }
+        self.next_update = next_update;
+    }
+}
+
+/// バックエンドがフレームや状態を通知するための窓口
+pub struct CaptureSink {
+    id: WindowId,
+    rx_cmd: Receiver<WindowCaptureCommand>,
     tx_msg: Sender<WindowCaptureMessage>,
-    hwnd: HWND,
-    tx_frame: Sender<CapturedFrame>,
+    frames: FramePoster,
+    pool: FramePool,
+    client_area: Option<Rect>,
+    // 最後に知らせたタイトル
+    title: Option<String>,
+    is_stop_requested: bool,
+    is_closed: bool,
+}
+
+impl CaptureSink {
+    pub fn id(&self) -> WindowId {
+        self.id
+    }
+
+    /// フレームを書き込むためのバッファを借りる。使い終わったバッファは自動的に返却される。
+    pub fn acquire_buffer(&self, len: usize) -> FrameBufferMut {
+        self.pool.acquire(len)
+    }
+
+    /// これから送るフレームのクライアント領域を知らせる
+    pub fn set_client_area(&mut self, client_area: Option<Rect>) {
+        self.client_area = client_area;
+    }
+
+    /// ウィンドウのタイトルを知らせる。前回と同じなら何もしない。フレームより先に届くよう、
+    /// 変わったタイトルのフレームを送る前に呼ぶこと
+    pub fn set_title(&mut self, title: String) {
+        if self.title.as_ref() == Some(&title) {
+            return;
+        }
+
+        self.title = Some(title.clone());
+        let _ = self
+            .tx_msg
+            .send(WindowCaptureMessage::TitleChanged { id: self.id, title });
+    }
+
+    pub fn on_frame_arrived(
+        &mut self,
+        width: u32,
+        height: u32,
+        stride: usize,
+        format: PixelFormat,
+        bytes: impl Into<FrameBuffer>,
+    ) {
+        let bytes = bytes.into();
+        debug_assert!(bytes.len() >= stride * height as usize);
+
+        self.frames.post(CapturedFrame {
+            id: self.id,
+            width,
+            height,
+            stride,
+            format,
+            timestamp: Instant::now(),
+            bytes,
+            client_area: self.client_area,
+        });
+    }
+
+    pub fn on_closed(&mut self) {
+        if self.is_closed {
+            return;
+        }
+
+        self.is_closed = true;
+        let _ = self
+            .tx_msg
+            .send(WindowCaptureMessage::Closed { id: self.id });
+    }
+
+    pub fn output(&self, message: String) {
+        let _ = self.tx_msg.send(WindowCaptureMessage::Output { message });
+    }
+
+    pub fn is_stop_requested(&mut self) -> bool {
+        while let Ok(cmd) = self.rx_cmd.try_recv() {
+            match cmd {
+                WindowCaptureCommand::Stop => self.is_stop_requested = true,
+            }
+        }
+
+        self.is_stop_requested
+    }
 }
 
-pub enum WindowCaptureCommand {}
+pub struct WindowCapture {
+    backend: Box<dyn CaptureBackend>,
+    sink: CaptureSink,
+}
+
+pub enum WindowCaptureCommand {
+    Stop,
+}
 
 pub enum WindowCaptureMessage {
     Output { message: String },
-    Closed { hwnd: HWND },
+    Closed { id: WindowId },
+    TitleChanged { id: WindowId, title: String },
 }
 
 impl WindowCapture {
     pub fn new(
-        hwnd: HWND,
-        tx_frame: Sender<CapturedFrame>,
+        id: WindowId,
+        backend: Box<dyn CaptureBackend>,
+        frames: FramePoster,
     ) -> (
         WindowCapture,
         Sender<WindowCaptureCommand>,
@@ -45,121 +339,95 @@ impl WindowCapture {
 
         (
             WindowCapture {
-                _rx_cmd: rx_cmd,
-                tx_msg,
-                hwnd,
-                tx_frame,
+                backend,
+                sink: CaptureSink {
+                    id,
+                    rx_cmd,
+                    tx_msg,
+                    frames,
+                    pool: FramePool::new(4),
+                    client_area: None,
+                    title: None,
+                    is_stop_requested: false,
+                    is_closed: false,
+                },
             },
             tx_cmd,
             rx_msg,
         )
     }
 
-    pub fn run(self) {
-        let tx_msg = self.tx_msg.clone();
-
-        let settings = WindowsCaptureSettings::new(
-            Window::from_hwnd(self.hwnd),
-            true,
-            false,
-            WindowCaptureArgs {
-                tx_msg: self.tx_msg,
-                hwnd: self.hwnd,
-                tx_frame: self.tx_frame,
-                fps: 60,
-            },
-        );
+    pub fn run(mut self) {
+        let id = self.sink.id;
+        let tx_msg = self.sink.tx_msg.clone();
 
-        if let Err(e) = Handler::start(settings) {
+        if let Err(e) = self.backend.start(self.sink) {
             let _ = tx_msg.send(WindowCaptureMessage::Output {
-                message: (format!("[{}] failed to capture: {e:?}", self.hwnd.0)),
+                message: (format!("[{id}] failed to capture: {e}")),
             });
-            let _ = tx_msg.send(WindowCaptureMessage::Closed { hwnd: self.hwnd });
+            let _ = tx_msg.send(WindowCaptureMessage::Closed { id });
         }
     }
 }
 
-pub struct WindowCaptureArgs {
-    tx_msg: Sender<WindowCaptureMessage>,
-    hwnd: HWND,
-    tx_frame: Sender<CapturedFrame>,
-    fps: u64,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::mock_window;
 
-pub struct Handler {
-    args: WindowCaptureArgs,
-    next_update: Instant,
-}
-
-impl Handler {
-    fn compute_next_update(&mut self) {
-        let mut next_update = self.next_update + Duration::from_millis(1000 / self.args.fps);
-        let now = Instant::now();
-        if now < next_update {
-            next_update = now + Duration::from_millis(1000 / self.args.fps);
+    fn frame(format: PixelFormat, stride: usize, bytes: Vec<u8>) -> CapturedFrame {
+        CapturedFrame {
+            id: mock_window(1),
+            width: 2,
+            height: 2,
+            stride,
+            format,
+            timestamp: Instant::now(),
+            bytes: bytes.into(),
+            client_area: None,
         }
-        self.next_update = next_update;
     }
-}
-
-impl WindowsCaptureHandler for Handler {
-    type Flags = WindowCaptureArgs;
 
-    fn new(args: Self::Flags) -> Self {
-        Self {
-            args,
-            next_update: Instant::now(),
-        }
+    #[test]
+    fn pacer_keeps_fps_in_range() {
+        assert_eq!(FramePacer::new(0).interval, Duration::from_secs(1));
+        assert_eq!(FramePacer::new(60).fps(), 60);
+        let fastest = FramePacer::new(100_000);
+        assert_eq!(fastest.fps(), FramePacer::MAX_FPS);
+        assert!(!fastest.interval.is_zero());
     }
 
-    fn on_frame_arrived(&mut self, frame: &Frame) {
-        if Instant::now() < self.next_update {
-            return;
-        }
+    #[test]
+    fn skips_row_padding() {
+        #[rustfmt::skip]
+        let frame = frame(PixelFormat::Rgba8, 12, vec![
+            1, 1, 1, 1, 2, 2, 2, 2, 0xEE, 0xEE, 0xEE, 0xEE,
+            3, 3, 3, 3, 4, 4, 4, 4, 0xEE, 0xEE, 0xEE, 0xEE,
+        ]);
 
-        let Ok(buffer) = frame.buffer() else {
-            let _ = self.args.tx_msg.send(WindowCaptureMessage::Output {
-                message: format!("[{}] failed to get frame buffer", self.args.hwnd.0),
-            });
-            return;
-        };
-
-        // バッファは画像幅を32の倍数に切り上げて送ってくるらしいので、バッファの幅を計算する。
-        let width32 = ((buffer.width() + 31) / 32 * 32) as usize;
+        assert!(!frame.is_packed_rgba8());
+        assert_eq!(frame.row(1), [3, 3, 3, 3, 4, 4, 4, 4]);
 
-        let pixels = buffer.pixels();
-
-        // 画像のうち「32の倍数に満たなかったあまり部分」には適当なごみデータが入っているようなの
-        // で、ごみデータ部分を削除する。
-        let mut bytes = Vec::with_capacity(
-            buffer.width() as usize * buffer.height() as usize * mem::size_of::<RGBA>(),
+        let packed = frame.to_rgba8();
+        assert!(packed.is_packed_rgba8());
+        assert_eq!(
+            *packed.bytes,
+            [1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4]
         );
+    }
 
-        for i in 0..buffer.height() as usize {
-            let start = i * width32;
-            let row_pixels = &pixels[start..(start + buffer.width() as usize)];
-            let row_bytes = unsafe {
-                slice::from_raw_parts(
-                    row_pixels as *const _ as *const u8,
-                    mem::size_of_val(row_pixels),
-                )
-            };
-            bytes.extend(row_bytes);
-        }
-
-        let _ = self.args.tx_frame.send(CapturedFrame {
-            hwnd: self.args.hwnd,
-            width: buffer.width(),
-            height: buffer.height(),
-            bytes,
-        });
+    #[test]
+    fn converts_bgrx_to_opaque_rgba() {
+        let frame = frame(PixelFormat::Bgrx8, 8, [10, 20, 30, 0].repeat(4));
 
-        self.compute_next_update();
+        assert_eq!(frame.pixel(1, 1), [30, 20, 10, 255]);
+        assert_eq!(*frame.to_rgba8().bytes, [30, 20, 10, 255].repeat(4));
     }
 
-    fn on_closed(&mut self) {
-        let _ = self.args.tx_msg.send(WindowCaptureMessage::Closed {
-            hwnd: self.args.hwnd,
-        });
+    #[test]
+    fn packed_rgba_is_shared_without_copy() {
+        let frame = frame(PixelFormat::Rgba8, 8, vec![7; 16]);
+
+        assert!(frame.to_rgba8().bytes.ptr_eq(&frame.bytes));
     }
 }
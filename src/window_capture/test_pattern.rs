@@ -0,0 +1,206 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::{CaptureBackend, CaptureSink, FramePacer, PixelFormat};
+use crate::draw;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TestPatternConfig {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u64,
+}
+
+impl TestPatternConfig {
+    // 大きすぎるとバッファを確保できず、速すぎるとフレームの間隔が 0 になってしまう
+    pub const MAX_SIZE: u32 = 8192;
+    pub const MAX_FPS: u64 = FramePacer::MAX_FPS;
+}
+
+impl Default for TestPatternConfig {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            fps: 60,
+        }
+    }
+}
+
+/// 実在するウィンドウの代わりに、カラーバーや時刻を焼き込んだフレームを生成する
+pub struct TestPatternBackend {
+    config: TestPatternConfig,
+}
+
+impl TestPatternBackend {
+    pub fn new(config: TestPatternConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl CaptureBackend for TestPatternBackend {
+    fn start(&mut self, mut sink: CaptureSink) -> Result<(), String> {
+        let mut pacer = FramePacer::new(self.config.fps);
+        let started = Instant::now();
+        let mut frame_count = 0;
+
+        loop {
+            if sink.is_stop_requested() {
+                return Ok(());
+            }
+
+            pacer.wait();
+
+            let TestPatternConfig { width, height, .. } = self.config;
+            let mut bytes = sink.acquire_buffer(width as usize * height as usize * 4);
+            render(&self.config, frame_count, SystemTime::now(), &mut bytes);
+            sink.on_frame_arrived(width, height, width as usize * 4, PixelFormat::Rgba8, bytes);
+
+            frame_count += 1;
+            pacer.compute_next_update();
+
+            // 実際のフレームレートを確認できるよう、ときどき報告する
+            if frame_count % (pacer.fps() * 10) == 0 {
+                let fps = frame_count as f64 / started.elapsed().as_secs_f64();
+                sink.output(format!("[{}] {fps:.2} fps", sink.id()));
+            }
+        }
+    }
+}
+
+const BARS: [[u8; 4]; 7] = [
+    [191, 191, 191, 255],
+    [191, 191, 0, 255],
+    [0, 191, 191, 255],
+    [0, 191, 0, 255],
+    [191, 0, 191, 255],
+    [191, 0, 0, 255],
+    [0, 0, 191, 255],
+];
+
+/// `bytes` は width * height の RGBA8。前のフレームの内容が残っていてもすべて塗り直す。
+fn render(config: &TestPatternConfig, frame_count: u64, now: SystemTime, bytes: &mut [u8]) {
+    let TestPatternConfig { width, height, .. } = *config;
+
+    // 上 6 割はカラーバー
+    let bars_height = height * 6 / 10;
+    for (i, color) in BARS.iter().enumerate() {
+        let x = width * i as u32 / BARS.len() as u32;
+        let next_x = width * (i as u32 + 1) / BARS.len() as u32;
+        draw::fill_rect(
+            bytes,
+            width,
+            height,
+            (x, 0, next_x - x, bars_height),
+            *color,
+        );
+    }
+
+    // その下はフレームごとに横へ流れるグラデーション
+    let gradient_height = height / 4;
+    let offset = frame_count * 4;
+    for y in bars_height..(bars_height + gradient_height).min(height) {
+        for x in 0..width {
+            let v = ((x as u64 + offset) * 256 / width.max(1) as u64 % 256) as u8;
+            let i = (y as usize * width as usize + x as usize) * 4;
+            bytes[i..i + 4].copy_from_slice(&[v, v, v, 255]);
+        }
+    }
+
+    // 残りにフレーム番号と壁時計の時刻を焼き込む
+    let text = format!("FRAME {frame_count:06} {}", wall_clock(now));
+    let text_top = bars_height + gradient_height;
+    let area_height = height.saturating_sub(text_top);
+    draw::fill_rect(
+        bytes,
+        width,
+        height,
+        (0, text_top, width, area_height),
+        [0, 0, 0, 255],
+    );
+    let scale = (area_height / (draw::LINE_HEIGHT + 2))
+        .min(width / (draw::text_width(&text, 1) + 2))
+        .max(1);
+    let text_y = text_top + area_height.saturating_sub(draw::LINE_HEIGHT * scale) / 2;
+    draw::draw_text(
+        bytes,
+        width,
+        height,
+        (scale, text_y),
+        scale,
+        &text,
+        [255, 255, 255, 255],
+    );
+}
+
+/// UTC の HH:MM:SS.mmm
+fn wall_clock(now: SystemTime) -> String {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const CONFIG: TestPatternConfig = TestPatternConfig {
+        width: 320,
+        height: 180,
+        fps: 30,
+    };
+
+    fn render_frame(frame_count: u64) -> Vec<u8> {
+        // 前のフレームの残りがあっても塗り直されることを確かめるため、0 以外で埋めておく
+        let mut bytes = vec![0x55; (CONFIG.width * CONFIG.height * 4) as usize];
+        render(&CONFIG, frame_count, UNIX_EPOCH, &mut bytes);
+        bytes
+    }
+
+    fn pixel(bytes: &[u8], x: u32, y: u32) -> &[u8] {
+        let i = ((y * CONFIG.width + x) * 4) as usize;
+        &bytes[i..i + 4]
+    }
+
+    #[test]
+    fn renders_color_bars() {
+        let bytes = render_frame(0);
+
+        assert_eq!(pixel(&bytes, 0, 0), BARS[0]);
+        assert_eq!(pixel(&bytes, CONFIG.width - 1, 0), BARS[6]);
+    }
+
+    #[test]
+    fn gradient_moves_between_frames() {
+        let y = CONFIG.height * 6 / 10 + 1;
+        let first = render_frame(0);
+        let second = render_frame(1);
+
+        assert_ne!(pixel(&first, 10, y), pixel(&second, 10, y));
+    }
+
+    #[test]
+    fn burns_in_frame_counter() {
+        let first = render_frame(0);
+        let second = render_frame(1);
+
+        let text_area = (CONFIG.height * 6 / 10 + CONFIG.height / 4) as usize;
+        let start = text_area * CONFIG.width as usize * 4;
+        assert!(first[start..].contains(&255));
+        assert!(!first[start..].contains(&0x55));
+        assert_ne!(first[start..], second[start..]);
+    }
+
+    #[test]
+    fn formats_wall_clock() {
+        let now = UNIX_EPOCH + Duration::from_millis(((13 * 60 + 4) * 60 + 5) * 1000 + 67);
+        assert_eq!(wall_clock(now), "13:04:05.067");
+    }
+}
@@ -0,0 +1,175 @@
+use std::{ffi::c_void, mem, slice};
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, POINT, RECT, WPARAM},
+    Graphics::{
+        Dwm::{DwmGetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS},
+        Gdi::ClientToScreen,
+    },
+    System::Threading::GetCurrentThreadId,
+    UI::WindowsAndMessaging::{GetClientRect, PostThreadMessageW, WM_QUIT},
+};
+use windows_capture::{
+    capture::{WindowsCaptureHandler, WindowsCaptureSettings},
+    frame::Frame,
+    window::Window,
+};
+
+use super::{CaptureBackend, CaptureSink, FramePacer, PixelFormat, Rect};
+use crate::window_list;
+
+pub struct Win32CaptureBackend {
+    fps: u64,
+}
+
+impl Win32CaptureBackend {
+    pub fn new(fps: u64) -> Self {
+        Self { fps }
+    }
+}
+
+impl CaptureBackend for Win32CaptureBackend {
+    fn start(&mut self, sink: CaptureSink) -> Result<(), String> {
+        let Some(hwnd) = sink.id().to_hwnd() else {
+            return Err(format!("{} is not a win32 window", sink.id()));
+        };
+
+        let settings = WindowsCaptureSettings::new(
+            Window::from_hwnd(hwnd),
+            true,
+            false,
+            WindowCaptureArgs {
+                sink,
+                hwnd,
+                fps: self.fps,
+            },
+        );
+
+        Handler::start(settings).map_err(|e| format!("{e:?}"))
+    }
+}
+
+pub struct WindowCaptureArgs {
+    sink: CaptureSink,
+    hwnd: HWND,
+    fps: u64,
+}
+
+pub struct Handler {
+    args: WindowCaptureArgs,
+    pacer: FramePacer,
+    // start のメッセージループを回しているスレッド。WM_QUIT を送ると start から戻る
+    thread_id: u32,
+    is_stopping: bool,
+}
+
+impl WindowsCaptureHandler for Handler {
+    type Flags = WindowCaptureArgs;
+
+    // start から、メッセージループを回すスレッドで呼ばれる
+    fn new(args: Self::Flags) -> Self {
+        Self {
+            pacer: FramePacer::new(args.fps),
+            args,
+            thread_id: unsafe { GetCurrentThreadId() },
+            is_stopping: false,
+        }
+    }
+
+    fn on_frame_arrived(&mut self, frame: &Frame) {
+        if self.is_stopping {
+            return;
+        }
+        if self.args.sink.is_stop_requested() {
+            self.stop();
+            return;
+        }
+
+        if !self.pacer.is_due() {
+            return;
+        }
+
+        let Ok(buffer) = frame.buffer() else {
+            self.args.sink.output(format!(
+                "[{}] failed to get frame buffer",
+                self.args.sink.id()
+            ));
+            return;
+        };
+
+        let pixels = buffer.pixels();
+        let height = buffer.height() as usize;
+        if height == 0 {
+            return;
+        }
+
+        // バッファの行末には詰め物が入っていることがあるので、全体の大きさから実際の行ピッチを求
+        // める。詰め物は取り除かずにそのまま渡す。
+        let pixel_bytes = unsafe {
+            slice::from_raw_parts(pixels.as_ptr() as *const u8, mem::size_of_val(pixels))
+        };
+        let stride = pixel_bytes.len() / height;
+
+        let mut bytes = self.args.sink.acquire_buffer(stride * height);
+        bytes.copy_from_slice(&pixel_bytes[..stride * height]);
+
+        // タイトルが変わっていたら、このフレームより先に知らせる
+        if let Ok(title) = window_list::window_title(self.args.sink.id()) {
+            self.args.sink.set_title(title);
+        }
+        let client_area = client_area(self.args.hwnd);
+        self.args.sink.set_client_area(client_area);
+        self.args.sink.on_frame_arrived(
+            buffer.width(),
+            buffer.height(),
+            stride,
+            PixelFormat::Rgba8,
+            bytes,
+        );
+
+        self.pacer.compute_next_update();
+    }
+
+    fn on_closed(&mut self) {
+        self.args.sink.on_closed();
+    }
+}
+
+impl Handler {
+    /// メッセージループを抜けさせる。キャプチャはそのあと start の中で閉じられる
+    fn stop(&mut self) {
+        self.is_stopping = true;
+        if let Err(e) = unsafe { PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) }
+        {
+            self.args
+                .sink
+                .output(format!("[{}] failed to stop: {e}", self.args.sink.id()));
+        }
+    }
+}
+
+/// キャプチャした画像の中でのクライアント領域。画像は見た目どおりの枠 (DWM の拡張フレーム) の範囲になる
+fn client_area(hwnd: HWND) -> Option<Rect> {
+    let mut bounds = RECT::default();
+    let mut client = RECT::default();
+    let mut origin = POINT::default();
+    unsafe {
+        DwmGetWindowAttribute(
+            hwnd,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut bounds as *mut RECT as *mut c_void,
+            mem::size_of::<RECT>() as u32,
+        )
+        .ok()?;
+        GetClientRect(hwnd, &mut client).ok()?;
+        if !ClientToScreen(hwnd, &mut origin).as_bool() {
+            return None;
+        }
+    }
+
+    Some(Rect {
+        x: (origin.x - bounds.left).max(0) as u32,
+        y: (origin.y - bounds.top).max(0) as u32,
+        width: client.right.max(0) as u32,
+        height: client.bottom.max(0) as u32,
+    })
+}
@@ -0,0 +1,324 @@
+use std::{error::Error, ptr};
+
+use x11rb::{
+    connection::{Connection, RequestConnection as _},
+    protocol::{
+        composite::{self, ConnectionExt as _, Redirect},
+        shm::{self, ConnectionExt as _},
+        xproto::{
+            ChangeWindowAttributesAux, ConnectionExt as _, Drawable, EventMask, ImageFormat,
+            ImageOrder, Pixmap, Window,
+        },
+        Event,
+    },
+    rust_connection::RustConnection,
+};
+
+use super::{CaptureBackend, CaptureSink, FramePacer, PixelFormat};
+use crate::{frame_pool::FrameBufferMut, window_list::WindowQuery};
+
+pub struct X11CaptureBackend {
+    fps: u64,
+}
+
+impl X11CaptureBackend {
+    pub fn new(fps: u64) -> Self {
+        Self { fps }
+    }
+}
+
+impl CaptureBackend for X11CaptureBackend {
+    fn start(&mut self, mut sink: CaptureSink) -> Result<(), String> {
+        let Some(window) = sink.id().to_x11() else {
+            return Err(format!("{} is not an X11 window", sink.id()));
+        };
+        let mut capturer = X11Capturer::new(window).map_err(|e| e.to_string())?;
+        capturer.run(&mut sink, self.fps).map_err(|e| e.to_string())
+    }
+}
+
+struct X11Capturer {
+    conn: RustConnection,
+    window: Window,
+    // タイトルの変化を、接続し直さずにこの接続で読む
+    query: WindowQuery,
+    lsb_first: bool,
+    has_composite: bool,
+    // XComposite が使えるときはウィンドウの内容を指すピクスマップ。隠れている間などは作れない
+    pixmap: Option<Pixmap>,
+    shm: Option<ShmSegment>,
+    has_shm: bool,
+}
+
+impl X11Capturer {
+    fn new(window: Window) -> Result<Self, Box<dyn Error>> {
+        let (conn, _) = x11rb::connect(None)?;
+        let lsb_first = conn.setup().image_byte_order == ImageOrder::LSB_FIRST;
+
+        conn.change_window_attributes(
+            window,
+            &ChangeWindowAttributesAux::new()
+                .event_mask(EventMask::STRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE),
+        )?
+        .check()?;
+        let query = WindowQuery::new(&conn)?;
+
+        let has_composite = conn
+            .extension_information(composite::X11_EXTENSION_NAME)?
+            .is_some();
+        if has_composite {
+            conn.composite_query_version(0, 4)?.reply()?;
+            // リダイレクトしておくと、ほかのウィンドウに隠れていても内容を取得できる
+            conn.composite_redirect_window(window, Redirect::AUTOMATIC)?
+                .check()?;
+        }
+
+        let has_shm = conn
+            .extension_information(shm::X11_EXTENSION_NAME)?
+            .is_some()
+            && conn.shm_query_version()?.reply().is_ok();
+
+        Ok(Self {
+            conn,
+            window,
+            query,
+            lsb_first,
+            has_composite,
+            pixmap: None,
+            shm: None,
+            has_shm,
+        })
+    }
+
+    fn run(&mut self, sink: &mut CaptureSink, fps: u64) -> Result<(), Box<dyn Error>> {
+        let mut pacer = FramePacer::new(fps);
+        self.report_title(sink);
+        self.try_refresh_pixmap(sink);
+
+        loop {
+            if sink.is_stop_requested() {
+                return Ok(());
+            }
+
+            while let Some(event) = self.conn.poll_for_event()? {
+                match event {
+                    Event::DestroyNotify(ev) if ev.window == self.window => {
+                        sink.on_closed();
+                        return Ok(());
+                    }
+                    // サイズが変わったり表示し直されたりすると、ピクスマップも作り直しになる
+                    Event::ConfigureNotify(ev) if ev.window == self.window => {
+                        self.try_refresh_pixmap(sink);
+                    }
+                    Event::MapNotify(ev) if ev.window == self.window => {
+                        self.try_refresh_pixmap(sink);
+                    }
+                    Event::PropertyNotify(ev)
+                        if ev.window == self.window && self.query.is_title(ev.atom) =>
+                    {
+                        self.report_title(sink);
+                    }
+                    _ => {}
+                }
+            }
+
+            pacer.wait();
+
+            match self.capture(sink) {
+                Ok((width, height, stride, bytes)) => {
+                    sink.on_frame_arrived(width, height, stride, PixelFormat::Bgrx8, bytes)
+                }
+                Err(e) => sink.output(format!("[{}] failed to get frame buffer: {e}", sink.id())),
+            }
+
+            pacer.compute_next_update();
+        }
+    }
+
+    /// 隠れている間は作れないので、失敗してもキャプチャは続ける。そのあいだはウィンドウから直接取る
+    fn try_refresh_pixmap(&mut self, sink: &CaptureSink) {
+        if !self.has_composite {
+            return;
+        }
+        if let Err(e) = self.refresh_pixmap() {
+            sink.output(format!("[{}] failed to redirect window: {e}", sink.id()));
+        }
+    }
+
+    fn refresh_pixmap(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(pixmap) = self.pixmap.take() {
+            self.conn.free_pixmap(pixmap)?;
+        }
+
+        let pixmap = self.conn.generate_id()?;
+        self.conn
+            .composite_name_window_pixmap(self.window, pixmap)?
+            .check()?;
+        self.pixmap = Some(pixmap);
+
+        Ok(())
+    }
+
+    fn report_title(&self, sink: &mut CaptureSink) {
+        match self.query.window_title(&self.conn, sink.id()) {
+            Ok(title) => sink.set_title(title),
+            Err(e) => sink.output(format!("[{}] failed to get title: {e}", sink.id())),
+        }
+    }
+
+    fn shm_get_image(
+        &mut self,
+        drawable: Drawable,
+        width: u16,
+        height: u16,
+        size: usize,
+    ) -> Result<&ShmSegment, Box<dyn Error>> {
+        if self.shm.as_ref().is_none_or(|shm| shm.size < size) {
+            if let Some(old) = self.shm.take() {
+                self.conn.shm_detach(old.seg)?;
+            }
+            self.shm = Some(ShmSegment::new(&self.conn, size)?);
+        }
+        let shm = self.shm.as_ref().unwrap();
+
+        self.conn
+            .shm_get_image(
+                drawable,
+                0,
+                0,
+                width,
+                height,
+                !0,
+                ImageFormat::Z_PIXMAP.into(),
+                shm.seg,
+                0,
+            )?
+            .reply()?;
+        Ok(shm)
+    }
+
+    fn capture(
+        &mut self,
+        sink: &CaptureSink,
+    ) -> Result<(u32, u32, usize, FrameBufferMut), Box<dyn Error>> {
+        let geometry = self.conn.get_geometry(self.window)?.reply()?;
+        let (width, height) = (geometry.width, geometry.height);
+        let drawable: Drawable = self.pixmap.unwrap_or(self.window);
+
+        let Some(format) = self
+            .conn
+            .setup()
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == geometry.depth && format.bits_per_pixel == 32)
+        else {
+            return Err(format!("unsupported depth {}", geometry.depth).into());
+        };
+
+        // 各行は scanline_pad ビットの倍数に揃えられている
+        let pad = format.scanline_pad as usize / 8;
+        let stride = (width as usize * 4).div_ceil(pad) * pad;
+        let size = stride * height as usize;
+        let mut bytes = sink.acquire_buffer(size);
+
+        let shm_error = if self.has_shm {
+            match self.shm_get_image(drawable, width, height, size) {
+                Ok(shm) => {
+                    unsafe { ptr::copy_nonoverlapping(shm.addr, bytes.as_mut_ptr(), size) };
+                    None
+                }
+                Err(e) => Some(e),
+            }
+        } else {
+            None
+        };
+
+        if !self.has_shm || shm_error.is_some() {
+            let reply = self
+                .conn
+                .get_image(ImageFormat::Z_PIXMAP, drawable, 0, 0, width, height, !0)?
+                .reply()?;
+            bytes.copy_from_slice(&reply.data[..size]);
+
+            // GetImage なら取れたので、共有メモリが使えない環境だった。以後は GetImage だけを使う
+            if let Some(e) = shm_error {
+                self.has_shm = false;
+                if let Some(shm) = self.shm.take() {
+                    let _ = self.conn.shm_detach(shm.seg);
+                }
+                sink.output(format!(
+                    "[{}] MIT-SHM is unavailable, using GetImage instead: {e}",
+                    sink.id()
+                ));
+            }
+        }
+
+        // ZPixmap は BGRX のまま渡す。MSBFirst のサーバーでは XRGB なので並びを揃える。
+        if !self.lsb_first {
+            for pixel in bytes.chunks_exact_mut(4) {
+                pixel.reverse();
+            }
+        }
+
+        Ok((width as u32, height as u32, stride, bytes))
+    }
+}
+
+impl Drop for X11Capturer {
+    fn drop(&mut self) {
+        if let Some(pixmap) = self.pixmap.take() {
+            let _ = self.conn.free_pixmap(pixmap);
+        }
+        if let Some(shm) = self.shm.take() {
+            let _ = self.conn.shm_detach(shm.seg);
+        }
+        let _ = self.conn.flush();
+    }
+}
+
+struct ShmSegment {
+    seg: shm::Seg,
+    addr: *const u8,
+    size: usize,
+}
+
+impl ShmSegment {
+    fn new(conn: &RustConnection, size: usize) -> Result<Self, Box<dyn Error>> {
+        let id = unsafe { libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600) };
+        if id < 0 {
+            return Err("shmget failed".into());
+        }
+
+        let addr = unsafe { libc::shmat(id, ptr::null(), libc::SHM_RDONLY) };
+        if addr as isize == -1 {
+            unsafe { libc::shmctl(id, libc::IPC_RMID, ptr::null_mut()) };
+            return Err("shmat failed".into());
+        }
+
+        let seg = conn.generate_id()?;
+        let attached = conn
+            .shm_attach(seg, id as u32, false)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|cookie| cookie.check().map_err(Into::into));
+
+        // サーバーがアタッチし終えたら削除予約しておけば、異常終了時にも残らない
+        unsafe { libc::shmctl(id, libc::IPC_RMID, ptr::null_mut()) };
+
+        if let Err(e) = attached {
+            unsafe { libc::shmdt(addr) };
+            return Err(e);
+        }
+
+        Ok(Self {
+            seg,
+            addr: addr as *const u8,
+            size,
+        })
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        unsafe { libc::shmdt(self.addr as *const _) };
+    }
+}
@@ -0,0 +1,137 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// どのバックエンドのウィンドウかを区別しつつ、ウィンドウを一意に指す ID。
+///
+/// 文字列表現は `x11:12345` のような `<backend>:<raw>` 形式。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WindowId {
+    backend: Backend,
+    raw: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Backend {
+    Win32,
+    X11,
+    Mock,
+    TestPattern,
+    // ウィンドウの代わりに出す画面
+    Placeholder,
+}
+
+impl Backend {
+    /// このプラットフォームで実際のウィンドウを扱うバックエンド
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            Backend::Win32
+        } else {
+            Backend::X11
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Win32 => "win32",
+            Backend::X11 => "x11",
+            Backend::Mock => "mock",
+            Backend::TestPattern => "testpattern",
+            Backend::Placeholder => "placeholder",
+        }
+    }
+}
+
+impl WindowId {
+    pub fn new(backend: Backend, raw: u64) -> Self {
+        Self { backend, raw }
+    }
+
+    pub fn backend(self) -> Backend {
+        self.backend
+    }
+
+    pub fn raw(self) -> u64 {
+        self.raw
+    }
+}
+
+#[cfg(windows)]
+impl WindowId {
+    pub fn from_hwnd(hwnd: windows::Win32::Foundation::HWND) -> Self {
+        Self::new(Backend::Win32, hwnd.0 as u64)
+    }
+
+    pub fn to_hwnd(self) -> Option<windows::Win32::Foundation::HWND> {
+        (self.backend == Backend::Win32)
+            .then_some(windows::Win32::Foundation::HWND(self.raw as isize))
+    }
+}
+
+#[cfg(unix)]
+impl WindowId {
+    pub fn from_x11(window: u32) -> Self {
+        Self::new(Backend::X11, window as u64)
+    }
+
+    pub fn to_x11(self) -> Option<u32> {
+        (self.backend == Backend::X11).then_some(self.raw as u32)
+    }
+}
+
+impl fmt::Display for WindowId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 表で桁をそろえられるように幅の指定に従う
+        f.pad(&format!("{}:{}", self.backend.name(), self.raw))
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            Backend::Win32,
+            Backend::X11,
+            Backend::Mock,
+            Backend::TestPattern,
+            Backend::Placeholder,
+        ]
+        .into_iter()
+        .find(|backend| backend.name() == s)
+        .ok_or_else(|| format!("unknown backend {s}"))
+    }
+}
+
+impl FromStr for WindowId {
+    type Err = String;
+
+    /// `<backend>:<raw>` のほか、バックエンドを省略した数値だけも受け付ける。
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (backend, raw) = match s.split_once(':') {
+            Some((backend, raw)) => (backend.parse()?, raw),
+            None => (Backend::native(), s),
+        };
+
+        let raw = match raw.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => raw.parse(),
+        }
+        .map_err(|_| format!("invalid window id {s}"))?;
+
+        Ok(Self::new(backend, raw))
+    }
+}
+
+impl Serialize for WindowId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for WindowId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,59 @@
+use crate::window_id::WindowId;
+
+#[cfg(windows)]
+mod win32;
+#[cfg(unix)]
+mod x11;
+
+#[cfg(unix)]
+pub use x11::WindowQuery;
+
+#[derive(Clone, Debug)]
+pub struct WindowInfo {
+    pub id: WindowId,
+    pub title: String,
+    pub class: String,
+    pub pid: Option<u32>,
+    // 実行ファイル名
+    pub process: Option<String>,
+    // ダイアログなどを持っているウィンドウ
+    pub owner: Option<WindowId>,
+}
+
+impl WindowInfo {
+    /// 何もわからないウィンドウ
+    pub fn unknown(id: WindowId) -> Self {
+        Self {
+            id,
+            title: String::new(),
+            class: String::new(),
+            pid: None,
+            process: None,
+            owner: None,
+        }
+    }
+}
+
+/// キャプチャ対象になりうる普通のトップレベルウィンドウを列挙する。
+pub fn enumerate_windows() -> Result<Vec<WindowInfo>, String> {
+    #[cfg(windows)]
+    return win32::enumerate_windows();
+    #[cfg(unix)]
+    return x11::enumerate_windows();
+}
+
+/// ウィンドウのタイトルだけを調べる。キャプチャ中に何度も呼ぶので window_info より軽い。
+pub fn window_title(id: WindowId) -> Result<String, String> {
+    #[cfg(windows)]
+    return win32::window_title(id);
+    #[cfg(unix)]
+    return x11::window_title(id);
+}
+
+/// ひとつのウィンドウのタイトルなどを調べる。
+pub fn window_info(id: WindowId) -> Result<WindowInfo, String> {
+    #[cfg(windows)]
+    return win32::window_info(id);
+    #[cfg(unix)]
+    return x11::window_info(id);
+}
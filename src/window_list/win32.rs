@@ -0,0 +1,126 @@
+use std::{ffi::OsString, os::windows::prelude::OsStringExt, path::PathBuf};
+
+use windows::{
+    core::PWSTR,
+    Win32::{
+        Foundation::{CloseHandle, BOOL, HWND, LPARAM},
+        System::Threading::{
+            OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+            PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+        UI::WindowsAndMessaging::{
+            EnumWindows, GetClassNameW, GetWindow, GetWindowLongW, GetWindowTextW,
+            GetWindowThreadProcessId, InternalGetWindowText, IsWindow, GWL_STYLE, GW_OWNER,
+        },
+    },
+};
+
+use super::WindowInfo;
+use crate::window_id::WindowId;
+
+pub(super) fn enumerate_windows() -> Result<Vec<WindowInfo>, String> {
+    let mut windows: Vec<HWND> = vec![];
+    unsafe {
+        EnumWindows(
+            Some(enumerate_callback),
+            LPARAM(&mut windows as *mut _ as isize),
+        )
+        .map_err(|e| e.to_string())?
+    };
+
+    // 普通のウィンドウに限る
+    Ok(windows
+        .into_iter()
+        .filter(|&hwnd| {
+            let style = unsafe { GetWindowLongW(hwnd, GWL_STYLE) }; // GWL_STYLE
+
+            // WS_VISIBLEとWS_CAPTION
+            (style & 0x10C00000) == 0x10C00000
+        })
+        .map(|hwnd| unsafe { query_window(hwnd) })
+        .collect())
+}
+
+pub(super) fn window_info(id: WindowId) -> Result<WindowInfo, String> {
+    let Some(hwnd) = id.to_hwnd() else {
+        return Err(format!("{id} is not a Win32 window"));
+    };
+
+    if !unsafe { IsWindow(hwnd) }.as_bool() {
+        return Err(format!("{id} does not exist"));
+    }
+
+    Ok(unsafe { query_window(hwnd) })
+}
+
+pub(super) fn window_title(id: WindowId) -> Result<String, String> {
+    let Some(hwnd) = id.to_hwnd() else {
+        return Err(format!("{id} is not a Win32 window"));
+    };
+
+    // GetWindowTextW は相手のウィンドウにメッセージを送るので、毎フレーム呼ぶと相手が固まって
+    // いるときに待たされる。こちらはシステムが覚えているタイトルを読むだけ
+    let mut title_u16 = vec![0; 1024];
+    let len = unsafe { InternalGetWindowText(hwnd, &mut title_u16) };
+    Ok(OsString::from_wide(&title_u16[..len.max(0) as usize])
+        .to_string_lossy()
+        .into_owned())
+}
+
+unsafe extern "system" fn enumerate_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let windows = unsafe { &mut *(lparam.0 as *mut Vec<HWND>) };
+    windows.push(hwnd);
+
+    BOOL(1)
+}
+
+unsafe fn query_window(hwnd: HWND) -> WindowInfo {
+    let title = window_text(hwnd);
+
+    let mut class_u16 = vec![0; 256];
+    let len = GetClassNameW(hwnd, &mut class_u16);
+    let class = OsString::from_wide(&class_u16[..len.max(0) as usize])
+        .to_string_lossy()
+        .into_owned();
+
+    let mut pid = 0;
+    GetWindowThreadProcessId(hwnd, Some(&mut pid));
+
+    let owner = GetWindow(hwnd, GW_OWNER);
+
+    WindowInfo {
+        id: WindowId::from_hwnd(hwnd),
+        title,
+        class,
+        pid: (pid != 0).then_some(pid),
+        process: (pid != 0).then(|| process_name(pid)).flatten(),
+        owner: (owner.0 != 0).then(|| WindowId::from_hwnd(owner)),
+    }
+}
+
+unsafe fn window_text(hwnd: HWND) -> String {
+    let mut title_u16 = vec![0; 1024];
+    let len = GetWindowTextW(hwnd, &mut title_u16);
+    OsString::from_wide(&title_u16[..len.max(0) as usize])
+        .to_string_lossy()
+        .into_owned()
+}
+
+unsafe fn process_name(pid: u32) -> Option<String> {
+    let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+
+    let mut path_u16 = vec![0; 1024];
+    let mut len = path_u16.len() as u32;
+    let res = QueryFullProcessImageNameW(
+        process,
+        PROCESS_NAME_WIN32,
+        PWSTR(path_u16.as_mut_ptr()),
+        &mut len,
+    );
+    let _ = CloseHandle(process);
+    res.ok()?;
+
+    let path = PathBuf::from(OsString::from_wide(&path_u16[..len as usize]));
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
@@ -0,0 +1,230 @@
+use std::{error::Error, fs};
+
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, Window},
+    rust_connection::RustConnection,
+};
+
+use super::WindowInfo;
+use crate::window_id::WindowId;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        UTF8_STRING,
+        _NET_CLIENT_LIST,
+        _NET_WM_NAME,
+        _NET_WM_PID,
+        _NET_WM_WINDOW_TYPE,
+        _NET_WM_WINDOW_TYPE_DESKTOP,
+        _NET_WM_WINDOW_TYPE_DOCK,
+        _NET_WM_WINDOW_TYPE_MENU,
+        _NET_WM_WINDOW_TYPE_DROPDOWN_MENU,
+        _NET_WM_WINDOW_TYPE_POPUP_MENU,
+        _NET_WM_WINDOW_TYPE_TOOLTIP,
+        _NET_WM_WINDOW_TYPE_NOTIFICATION,
+        _NET_WM_WINDOW_TYPE_COMBO,
+        _NET_WM_WINDOW_TYPE_DND,
+    }
+}
+
+pub(super) fn enumerate_windows() -> Result<Vec<WindowInfo>, String> {
+    enumerate_windows_inner().map_err(|e| e.to_string())
+}
+
+pub(super) fn window_info(id: WindowId) -> Result<WindowInfo, String> {
+    let (conn, _) = x11rb::connect(None).map_err(|e| e.to_string())?;
+    WindowQuery::new(&conn)?.window_info(&conn, id)
+}
+
+pub(super) fn window_title(id: WindowId) -> Result<String, String> {
+    let (conn, _) = x11rb::connect(None).map_err(|e| e.to_string())?;
+    WindowQuery::new(&conn)?.window_title(&conn, id)
+}
+
+/// 接続を開いたまま何度も調べるときに使う。アトムは最初に一度だけ問い合わせる
+pub struct WindowQuery {
+    atoms: Atoms,
+}
+
+impl WindowQuery {
+    pub fn new(conn: &RustConnection) -> Result<Self, String> {
+        let atoms = (|| -> Result<_, Box<dyn Error>> { Ok(Atoms::new(conn)?.reply()?) })()
+            .map_err(|e| e.to_string())?;
+        Ok(Self { atoms })
+    }
+
+    pub fn window_info(&self, conn: &RustConnection, id: WindowId) -> Result<WindowInfo, String> {
+        let Some(window) = id.to_x11() else {
+            return Err(format!("{id} is not an X11 window"));
+        };
+
+        query_window(conn, window, &self.atoms).map_err(|e| e.to_string())
+    }
+
+    pub fn window_title(&self, conn: &RustConnection, id: WindowId) -> Result<String, String> {
+        let Some(window) = id.to_x11() else {
+            return Err(format!("{id} is not an X11 window"));
+        };
+
+        query_title(conn, window, &self.atoms).map_err(|e| e.to_string())
+    }
+
+    /// タイトルが入っているプロパティか
+    pub fn is_title(&self, atom: Atom) -> bool {
+        atom == self.atoms._NET_WM_NAME || atom == Atom::from(AtomEnum::WM_NAME)
+    }
+}
+
+fn enumerate_windows_inner() -> Result<Vec<WindowInfo>, Box<dyn Error>> {
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen_num].root;
+    let atoms = Atoms::new(&conn)?.reply()?;
+
+    // ドックやメニューなど、キャプチャしても仕方がないもの
+    let ignored_types = [
+        atoms._NET_WM_WINDOW_TYPE_DESKTOP,
+        atoms._NET_WM_WINDOW_TYPE_DOCK,
+        atoms._NET_WM_WINDOW_TYPE_MENU,
+        atoms._NET_WM_WINDOW_TYPE_DROPDOWN_MENU,
+        atoms._NET_WM_WINDOW_TYPE_POPUP_MENU,
+        atoms._NET_WM_WINDOW_TYPE_TOOLTIP,
+        atoms._NET_WM_WINDOW_TYPE_NOTIFICATION,
+        atoms._NET_WM_WINDOW_TYPE_COMBO,
+        atoms._NET_WM_WINDOW_TYPE_DND,
+    ];
+
+    let clients = conn
+        .get_property(
+            false,
+            root,
+            atoms._NET_CLIENT_LIST,
+            AtomEnum::WINDOW,
+            0,
+            u32::MAX,
+        )?
+        .reply()?;
+    let Some(clients) = clients.value32() else {
+        return Err("_NET_CLIENT_LIST is not supported by the window manager".into());
+    };
+
+    // 調べている間に閉じられたウィンドウは飛ばす
+    let mut windows = vec![];
+    for window in clients {
+        let Ok(types) = get_property32(&conn, window, atoms._NET_WM_WINDOW_TYPE, AtomEnum::ATOM)
+        else {
+            continue;
+        };
+        if types.iter().any(|ty| ignored_types.contains(ty)) {
+            continue;
+        }
+
+        if let Ok(info) = query_window(&conn, window, &atoms) {
+            windows.push(info);
+        }
+    }
+
+    Ok(windows)
+}
+
+fn query_window(
+    conn: &RustConnection,
+    window: Window,
+    atoms: &Atoms,
+) -> Result<WindowInfo, Box<dyn Error>> {
+    let pid = get_property32(conn, window, atoms._NET_WM_PID, AtomEnum::CARDINAL)?
+        .first()
+        .copied();
+    // ダイアログは WM_TRANSIENT_FOR で親ウィンドウを指している
+    let owner = get_property32(
+        conn,
+        window,
+        AtomEnum::WM_TRANSIENT_FOR.into(),
+        AtomEnum::WINDOW,
+    )?
+    .first()
+    .copied()
+    .filter(|&owner| owner != x11rb::NONE);
+
+    Ok(WindowInfo {
+        id: WindowId::from_x11(window),
+        title: query_title(conn, window, atoms)?,
+        class: window_class(conn, window)?,
+        pid,
+        process: pid.and_then(process_name),
+        owner: owner.map(WindowId::from_x11),
+    })
+}
+
+/// ほかのマシンのクライアントだと、たまたま同じ PID の別のプロセスを指してしまうこともある
+fn process_name(pid: u32) -> Option<String> {
+    if let Ok(exe) = fs::read_link(format!("/proc/{pid}/exe")) {
+        if let Some(name) = exe.file_name() {
+            return Some(name.to_string_lossy().into_owned());
+        }
+    }
+
+    // 権限がなくて exe を読めなくても comm なら読める (15 文字までに切り詰められている)
+    let comm = fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(comm.trim_end().to_owned())
+}
+
+fn query_title(
+    conn: &RustConnection,
+    window: Window,
+    atoms: &Atoms,
+) -> Result<String, Box<dyn Error>> {
+    let title = get_property8(conn, window, atoms._NET_WM_NAME, atoms.UTF8_STRING)?;
+    if !title.is_empty() {
+        return Ok(String::from_utf8_lossy(&title).into_owned());
+    }
+
+    // 古いクライアントは WM_NAME しか設定しない
+    let title = get_property8(
+        conn,
+        window,
+        AtomEnum::WM_NAME.into(),
+        AtomEnum::STRING.into(),
+    )?;
+    Ok(String::from_utf8_lossy(&title).into_owned())
+}
+
+fn window_class(conn: &RustConnection, window: Window) -> Result<String, Box<dyn Error>> {
+    // WM_CLASS はインスタンス名とクラス名が NUL 区切りで入っている
+    let value = get_property8(
+        conn,
+        window,
+        AtomEnum::WM_CLASS.into(),
+        AtomEnum::STRING.into(),
+    )?;
+    let class = value
+        .split(|&b| b == 0)
+        .filter(|part| !part.is_empty())
+        .nth(1)
+        .unwrap_or_default();
+    Ok(String::from_utf8_lossy(class).into_owned())
+}
+
+fn get_property8(
+    conn: &RustConnection,
+    window: Window,
+    property: Atom,
+    ty: Atom,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let reply = conn
+        .get_property(false, window, property, ty, 0, u32::MAX)?
+        .reply()?;
+    Ok(reply.value8().map(Iterator::collect).unwrap_or_default())
+}
+
+fn get_property32(
+    conn: &RustConnection,
+    window: Window,
+    property: Atom,
+    ty: AtomEnum,
+) -> Result<Vec<u32>, Box<dyn Error>> {
+    let reply = conn
+        .get_property(false, window, property, ty, 0, u32::MAX)?
+        .reply()?;
+    Ok(reply.value32().map(Iterator::collect).unwrap_or_default())
+}